@@ -1,70 +1,388 @@
-use clap::{AppSettings, Parser, Subcommand};
+use clap::{AppSettings, ArgGroup, Args, Parser, Subcommand};
+
+use crate::metadata_dir::FileFormat;
+use crate::output::OutputFormat;
+use crate::types::TableKind;
 
 /// A CLI for managing Hasura GraphQL Engine
 #[derive(Parser)]
 #[clap(name = "hasurautils")]
 #[clap(about = "A CLI for managing Hasura GraphQL Engine", long_about = None)]
 pub struct App {
+    /// Print the metadata requests that would be sent without sending them
+    #[clap(long, global = true)]
+    pub dry_run: bool,
+
+    /// Output format
+    #[clap(long, global = true, arg_enum, default_value = "table")]
+    pub output: OutputFormat,
+
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+
     #[clap(subcommand)]
     pub command: Commands,
 }
 
+/// Connection settings. Flags take precedence over env vars, which take
+/// precedence over the selected profile of the config file.
+#[derive(Args)]
+pub struct ConfigArgs {
+    /// Path of the config file [default: hasurautils.toml]
+    #[clap(long, global = true)]
+    pub config: Option<String>,
+
+    /// Profile of the config file to use [env: HASURA_UTILS_PROFILE]
+    #[clap(long, global = true)]
+    pub profile: Option<String>,
+
+    /// URL of the Hasura instance [env: HASURA_DATA_API_URL]
+    #[clap(long, global = true)]
+    pub endpoint: Option<String>,
+
+    /// Admin secret of the Hasura instance [env: HASURA_ADMIN_SECRET]
+    #[clap(long, global = true)]
+    pub admin_secret: Option<String>,
+
+    /// Name of the database source, repeat to run against several sources [env: HASURA_UTILS_SOURCE]
+    #[clap(long, global = true, multiple_occurrences = true)]
+    pub source: Vec<String>,
+
+    /// Run against every source of the metadata
+    #[clap(long, global = true, conflicts_with = "source")]
+    pub all_sources: bool,
+
+    /// Database driver, `pg`, `citus`, `cockroach` or `mssql` [env: HASURA_UTILS_DRIVER]
+    #[clap(long, global = true)]
+    pub driver: Option<String>,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Track table(s)
     #[clap(setting(AppSettings::ArgRequiredElseHelp))]
+    #[clap(group(ArgGroup::new("table").required(true).args(&["name", "all", "list"])))]
     TrackTable {
         /// Name of the table to track
-        #[clap(short, long, group = "table")]
+        #[clap(short, long, requires = "schema")]
         name: Option<String>,
 
-        /// Schema of the table to track
-        #[clap(short, long, requires = "name")]
+        /// Schema of the table to track, or with --all/--list the only schema to consider
+        #[clap(short, long)]
         schema: Option<String>,
 
         /// If it is should track all trackable tables in the database
-        #[clap(short, long, group = "table")]
+        #[clap(short, long)]
         all: bool,
 
-        /// names of tables to ignore
+        /// names of tables to ignore, either `name` (in every schema) or `schema.name`
         #[clap(short, long, requires = "all")]
         ignore: Option<Vec<String>>,
 
         /// list untracked tables
-        #[clap(short, long, group = "table")]
+        #[clap(short, long)]
         list: bool,
+
+        /// Kinds of relations to track or list
+        #[clap(short, long, arg_enum, use_delimiter = true, default_value = "table")]
+        kinds: Vec<TableKind>,
+
+        /// Only include objects matching these `schema.table` globs, e.g. `public.*`
+        #[clap(long, use_delimiter = true, conflicts_with = "name")]
+        include: Vec<String>,
+
+        /// Exclude objects matching these `schema.table` globs, e.g. `archive.*,*.tmp_*`
+        #[clap(long, use_delimiter = true, conflicts_with = "name")]
+        exclude: Vec<String>,
+    },
+    /// Untrack table(s)
+    #[clap(setting(AppSettings::ArgRequiredElseHelp))]
+    #[clap(group(ArgGroup::new("table").required(true).args(&["name", "all-in-schema"])))]
+    UntrackTable {
+        /// Name of the table to untrack
+        #[clap(short, long, requires = "schema")]
+        name: Option<String>,
+
+        /// Schema of the table to untrack
+        #[clap(short, long, requires = "name")]
+        schema: Option<String>,
+
+        /// Untrack every tracked table in this schema
+        #[clap(long)]
+        all_in_schema: Option<String>,
+
+        /// Also drop the relationships and permissions depending on the table(s)
+        #[clap(short, long)]
+        cascade: bool,
     },
     /// Track relationships(s)
     #[clap(setting(AppSettings::ArgRequiredElseHelp))]
+    #[clap(group(ArgGroup::new("relationship").required(true).args(&["name", "all"])))]
     TrackRel {
         /// Name of the table with the relationships to track
-        #[clap(short, long, group = "relationship")]
+        #[clap(short, long, requires = "schema")]
         name: Option<String>,
 
-        /// Schema of the table that has the relationships
-        #[clap(short, long, requires = "name")]
+        /// Schema of the table that has the relationships, or with --all the only schema to consider
+        #[clap(short, long)]
         schema: Option<String>,
 
         /// If it is should track all trackable relationships in the database
-        #[clap(short, long, group = "relationship")]
+        #[clap(short, long)]
         all: bool,
+
+        /// Only include objects matching these `schema.table` globs, e.g. `public.*`
+        #[clap(long, use_delimiter = true, conflicts_with = "name")]
+        include: Vec<String>,
+
+        /// Exclude objects matching these `schema.table` globs, e.g. `archive.*,*.tmp_*`
+        #[clap(long, use_delimiter = true, conflicts_with = "name")]
+        exclude: Vec<String>,
     },
     /// Track functions(s)
     #[clap(setting(AppSettings::ArgRequiredElseHelp))]
+    #[clap(group(ArgGroup::new("function").required(true).args(&["name", "all"])))]
     TrackFn {
         /// Name of the function to track
-        #[clap(short, long, group = "function")]
+        #[clap(short, long, requires = "schema")]
         name: Option<String>,
 
-        /// Schema of the function to track
-        #[clap(short, long, requires = "name")]
+        /// Schema of the function to track, or with --all the only schema to consider
+        #[clap(short, long)]
         schema: Option<String>,
 
         /// If it is should track all trackable functions in the database
-        #[clap(short, long, group = "function")]
+        #[clap(short, long)]
         all: bool,
+
+        /// Only include objects matching these `schema.table` globs, e.g. `public.*`
+        #[clap(long, use_delimiter = true, conflicts_with = "name")]
+        include: Vec<String>,
+
+        /// Exclude objects matching these `schema.table` globs, e.g. `archive.*,*.tmp_*`
+        #[clap(long, use_delimiter = true, conflicts_with = "name")]
+        exclude: Vec<String>,
+    },
+    /// Drop relationships whose foreign key no longer exists
+    PruneRels {
+        /// Also drop the permissions depending on the relationships
+        #[clap(short, long)]
+        cascade: bool,
     },
-    /// Check health of the Hasura instance
+    /// Untrack dropped objects and track new tables, relationships and functions
+    Sync {
+        /// The only schema to consider
+        #[clap(short, long)]
+        schema: Option<String>,
+
+        /// Also drop the relationships and permissions depending on the dropped objects
+        #[clap(short, long)]
+        cascade: bool,
+
+        /// Kinds of relations to track
+        #[clap(short, long, arg_enum, use_delimiter = true, default_value = "table")]
+        kinds: Vec<TableKind>,
+
+        /// Only include objects matching these `schema.table` globs, e.g. `public.*`
+        #[clap(long, use_delimiter = true)]
+        include: Vec<String>,
+
+        /// Exclude objects matching these `schema.table` globs, e.g. `archive.*,*.tmp_*`
+        #[clap(long, use_delimiter = true)]
+        exclude: Vec<String>,
+    },
+    /// Check health and metadata consistency of the Hasura instance
+    Doctor {
+        /// Treat warnings as failures
+        #[clap(long)]
+        strict: bool,
+    },
+    /// Export and manage the whole metadata
     #[clap(setting(AppSettings::ArgRequiredElseHelp))]
-    Doctor,
+    Metadata {
+        #[clap(subcommand)]
+        command: MetadataCommands,
+    },
+    /// Add, update, remove, list and test database sources
+    #[clap(setting(AppSettings::ArgRequiredElseHelp))]
+    Source {
+        #[clap(subcommand)]
+        command: SourceCommands,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SourceCommands {
+    /// Add a database source
+    #[clap(group(ArgGroup::new("database").required(true).args(&["database-url", "database-url-env"])))]
+    Add {
+        /// Name of the source
+        #[clap(short, long)]
+        name: String,
+
+        #[clap(flatten)]
+        connection: ConnectionArgs,
+    },
+    /// Change the connection settings of a source, keeping the settings not given
+    Update {
+        /// Name of the source
+        #[clap(short, long)]
+        name: String,
+
+        #[clap(flatten)]
+        connection: ConnectionArgs,
+    },
+    /// Remove a source from the metadata
+    Remove {
+        /// Name of the source
+        #[clap(short, long)]
+        name: String,
+
+        /// Also drop the tables, relationships and permissions tracked in the source
+        #[clap(short, long)]
+        cascade: bool,
+    },
+    /// List the sources of the metadata
+    List,
+    /// Run `SELECT 1` on every source
+    Test,
+}
+
+/// Connection settings of a source
+#[derive(Args)]
+pub struct ConnectionArgs {
+    /// Database URL
+    #[clap(long, conflicts_with = "database-url-env")]
+    pub database_url: Option<String>,
+
+    /// Env var of the Hasura server holding the database URL
+    #[clap(long)]
+    pub database_url_env: Option<String>,
+
+    /// Maximum number of connections in the pool
+    #[clap(long)]
+    pub max_connections: Option<u32>,
+
+    /// Seconds after which an idle connection is closed
+    #[clap(long)]
+    pub idle_timeout: Option<u32>,
+
+    /// Seconds after which a connection is closed, even if active
+    #[clap(long)]
+    pub connection_lifetime: Option<u32>,
+
+    /// Seconds to wait for a connection of the pool
+    #[clap(long)]
+    pub pool_timeout: Option<u32>,
+
+    /// Number of retries when connecting
+    #[clap(long)]
+    pub retries: Option<u32>,
+
+    /// Transaction isolation level: read-committed, repeatable-read or serializable
+    #[clap(long)]
+    pub isolation_level: Option<String>,
+
+    /// URL of a read replica, repeat for several replicas
+    #[clap(long, multiple_occurrences = true)]
+    pub read_replica_url: Vec<String>,
+
+    /// Env var holding the URL of a read replica, repeat for several replicas
+    #[clap(long, multiple_occurrences = true)]
+    pub read_replica_env: Vec<String>,
+}
+
+#[derive(Subcommand)]
+pub enum MetadataCommands {
+    /// Write the metadata to a directory with one file per source, table and function
+    Export {
+        /// Directory to write the metadata to
+        #[clap(short, long)]
+        dir: String,
+
+        /// Format of the written files
+        #[clap(long, arg_enum, default_value = "yaml")]
+        format: FileFormat,
+    },
+    /// Replace the metadata with the contents of an exported directory
+    Apply {
+        /// Directory to read the metadata from
+        #[clap(short, long)]
+        dir: String,
+
+        /// Format of the files to read
+        #[clap(long, arg_enum, default_value = "yaml")]
+        format: FileFormat,
+
+        /// Apply the metadata even if some of its objects are inconsistent
+        #[clap(long)]
+        allow_inconsistent_metadata: bool,
+    },
+    /// Show what applying an exported directory or another server's metadata would
+    /// change, exiting with 6 when anything differs and 0 when nothing does
+    #[clap(group(ArgGroup::new("target").required(true).args(&["dir", "against-profile"])))]
+    Diff {
+        /// Directory of exported metadata to compare with
+        #[clap(short, long)]
+        dir: Option<String>,
+
+        /// Format of the files to read
+        #[clap(long, arg_enum, default_value = "yaml")]
+        format: FileFormat,
+
+        /// Profile of the config file whose server to compare with
+        #[clap(long)]
+        against_profile: Option<String>,
+    },
+    /// List inconsistent objects grouped by source and type, or repair them
+    Inconsistencies {
+        /// Drop every inconsistent object from the metadata
+        #[clap(long, conflicts_with = "reload")]
+        drop: bool,
+
+        /// Reload the sources and remote schemas with inconsistent objects
+        #[clap(long)]
+        reload: bool,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<App, clap::Error> {
+        App::try_parse_from([&["hasurautils"], args].concat())
+    }
+
+    #[test]
+    fn track_commands_require_a_selector() {
+        for command in ["track-table", "track-rel", "track-fn"] {
+            assert!(parse(&[command, "--schema", "public"]).is_err());
+            assert!(parse(&[command, "--all", "--schema", "public"]).is_ok());
+        }
+    }
+
+    #[test]
+    fn name_requires_schema() {
+        assert!(parse(&["track-table", "--name", "users"]).is_err());
+        assert!(parse(&["track-rel", "--name", "users"]).is_err());
+        assert!(parse(&["track-fn", "--name", "search", "--schema", "public"]).is_ok());
+    }
+
+    #[test]
+    fn untrack_table_requires_a_table() {
+        assert!(parse(&["untrack-table", "--cascade"]).is_err());
+        assert!(parse(&["untrack-table", "--name", "users"]).is_err());
+        assert!(parse(&["untrack-table", "--name", "users", "--schema", "public"]).is_ok());
+        assert!(parse(&["untrack-table", "--all-in-schema", "archive", "--cascade"]).is_ok());
+    }
+
+    #[test]
+    fn filters_are_rejected_with_name() {
+        for command in ["track-table", "track-rel", "track-fn"] {
+            let name = [command, "--name", "users", "--schema", "public"];
+            assert!(parse(&[&name[..], &["--include", "public.*"]].concat()).is_err());
+            assert!(parse(&[command, "--all", "--exclude", "archive.*"]).is_ok());
+        }
+    }
 }
@@ -0,0 +1,172 @@
+use std::{collections::HashMap, fs, path::Path, process::Command};
+
+use serde::Deserialize;
+
+use crate::error::HasuraUtilsError;
+
+pub const DEFAULT_CONFIG_FILE: &str = "hasurautils.toml";
+
+/// Contents of `hasurautils.toml`
+///
+/// ```toml
+/// default_profile = "dev"
+///
+/// [profiles.dev]
+/// endpoint = "http://localhost:8080"
+/// admin_secret = "secret"
+/// source = "default"
+///
+/// [profiles.prod]
+/// endpoint = "https://hasura.example.com"
+/// admin_secret_command = "pass show hasura/prod"
+/// source = "default"
+/// driver = "pg"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct ConfigFile {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Profile {
+    pub endpoint: Option<String>,
+    pub admin_secret: Option<String>,
+    /// Shell command printing the admin secret, used when `admin_secret` is not set
+    pub admin_secret_command: Option<String>,
+    pub source: Option<String>,
+    pub driver: Option<String>,
+}
+
+impl ConfigFile {
+    /// Reads the config file at `path`, or `hasurautils.toml` in the working
+    /// directory when no path is given. A missing default file is an empty config.
+    pub fn load(path: Option<&str>) -> Result<Self, HasuraUtilsError> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => DEFAULT_CONFIG_FILE,
+            None => return Ok(Self::default()),
+        };
+        let content = fs::read_to_string(path).map_err(|err| {
+            HasuraUtilsError::InvalidConfig(format!("unable to read config file {path}: {err}"))
+        })?;
+        toml::from_str(&content).map_err(|err| {
+            HasuraUtilsError::InvalidConfig(format!("invalid config file {path}: {err}"))
+        })
+    }
+
+    /// The named profile, falling back to `default_profile`. No profile at all
+    /// gives an empty one so that env vars and flags alone keep working.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, HasuraUtilsError> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => self.profiles.get(name).cloned().ok_or_else(|| {
+                HasuraUtilsError::InvalidConfig(format!(
+                    "profile {name} is not defined in the config file"
+                ))
+            }),
+            None => Ok(Profile::default()),
+        }
+    }
+}
+
+impl Profile {
+    /// `admin_secret`, or the trimmed output of `admin_secret_command`
+    pub fn admin_secret(&self) -> Result<Option<String>, HasuraUtilsError> {
+        if let Some(secret) = &self.admin_secret {
+            return Ok(Some(secret.to_string()));
+        }
+        let command = match &self.admin_secret_command {
+            Some(command) => command,
+            None => return Ok(None),
+        };
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .map_err(|err| {
+                HasuraUtilsError::InvalidConfig(format!(
+                    "unable to run admin_secret_command: {err}"
+                ))
+            })?;
+        if !output.status.success() {
+            return Err(HasuraUtilsError::InvalidConfig(format!(
+                "admin_secret_command exited with {}",
+                output.status
+            )));
+        }
+        Ok(Some(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(content: &str) -> ConfigFile {
+        toml::from_str(content).unwrap()
+    }
+
+    fn profile(admin_secret_command: &str) -> Profile {
+        Profile {
+            admin_secret_command: Some(admin_secret_command.to_string()),
+            ..Profile::default()
+        }
+    }
+
+    #[test]
+    fn profile_falls_back_to_the_default_profile() {
+        let config = config(
+            r#"
+            default_profile = "dev"
+            [profiles.dev]
+            source = "default"
+            [profiles.prod]
+            source = "warehouse"
+            "#,
+        );
+        assert_eq!(config.profile(None).unwrap().source.unwrap(), "default");
+        assert_eq!(
+            config.profile(Some("prod")).unwrap().source.unwrap(),
+            "warehouse"
+        );
+        assert!(matches!(
+            config.profile(Some("staging")),
+            Err(HasuraUtilsError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn profile_is_empty_without_a_default() {
+        let profile = config("").profile(None).unwrap();
+        assert!(profile.endpoint.is_none() && profile.source.is_none());
+        let config = config(r#"default_profile = "dev""#);
+        assert!(config.profile(None).is_err());
+    }
+
+    #[test]
+    fn admin_secret_prefers_the_literal_value() {
+        let profile = Profile {
+            admin_secret: Some("literal".to_string()),
+            ..profile("echo command")
+        };
+        assert_eq!(profile.admin_secret().unwrap().unwrap(), "literal");
+        assert!(Profile::default().admin_secret().unwrap().is_none());
+    }
+
+    #[test]
+    fn admin_secret_command_output_is_trimmed() {
+        let secret = profile("printf '  s3cret\\n'").admin_secret().unwrap();
+        assert_eq!(secret.unwrap(), "s3cret");
+    }
+
+    #[test]
+    fn failing_admin_secret_command_is_a_config_error() {
+        assert!(matches!(
+            profile("exit 3").admin_secret(),
+            Err(HasuraUtilsError::InvalidConfig(_))
+        ));
+    }
+}
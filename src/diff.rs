@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::env::EnvVars;
+use crate::error::HasuraUtilsError;
+use crate::metadata_dir::{qualified_name, MetadataDir};
+use crate::util::HasuraUtils;
+
+/// Exit code of `metadata diff` when the metadata differs, distinct from the codes
+/// of errors (see `HasuraUtilsError::exit_code`) and from clap's 2 for usage errors
+pub const DRIFT_EXIT_CODE: i32 = 6;
+
+/// Keys of a table entry holding relationships and permissions, with the label
+/// and the key identifying each item
+const TABLE_ITEMS: [(&str, &str, &str); 7] = [
+    ("object_relationships", "object relationship", "name"),
+    ("array_relationships", "array relationship", "name"),
+    ("remote_relationships", "remote relationship", "name"),
+    ("insert_permissions", "insert permission", "role"),
+    ("select_permissions", "select permission", "role"),
+    ("update_permissions", "update permission", "role"),
+    ("delete_permissions", "delete permission", "role"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A metadata object that differs between two metadata documents
+#[derive(Debug, Serialize)]
+pub struct DiffEntry {
+    pub change: Change,
+    /// Path of the object, e.g. `["source default", "table public.users", "select permission user"]`
+    pub object: Vec<String>,
+    /// Keys whose value differs, for changed objects
+    pub fields: Vec<String>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+/// Metadata to compare the live metadata with
+pub enum DiffTarget<'a> {
+    Dir(MetadataDir),
+    /// Server of a profile of the config file
+    Profile {
+        config: Option<&'a str>,
+        name: &'a str,
+    },
+}
+
+impl HasuraUtils {
+    /// Changes that applying `target` would make to the live metadata
+    pub async fn diff_metadata(
+        &self,
+        target: DiffTarget<'_>,
+    ) -> Result<Vec<DiffEntry>, HasuraUtilsError> {
+        let live = self.export_metadata().await?;
+        let target = match target {
+            DiffTarget::Dir(dir) => dir.read()?,
+            DiffTarget::Profile { config, name } => {
+                let env = EnvVars::for_profile(config, name)?;
+                let other = HasuraUtils {
+                    client: env.make_client()?,
+                    env,
+                    dry_run: self.dry_run,
+                };
+                other.export_metadata().await?
+            }
+        };
+        Ok(diff(&live, &target))
+    }
+}
+
+/// Objects added, removed or changed from `before` to `after`, sorted by path.
+/// Sources, tables, functions, relationships, permissions and named top level
+/// items such as actions are compared one by one, regardless of their order.
+pub fn diff(before: &Value, after: &Value) -> Vec<DiffEntry> {
+    let before = objects(before);
+    let after = objects(after);
+    let paths = before.keys().chain(after.keys()).collect::<BTreeSet<_>>();
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let (b, a) = (before.get(path), after.get(path));
+            let change = match (b, a) {
+                (Some(_), None) => Change::Removed,
+                (None, Some(_)) => Change::Added,
+                (Some(b), Some(a)) if b != a => Change::Changed,
+                _ => return None,
+            };
+            Some(DiffEntry {
+                change,
+                object: path.clone(),
+                fields: b.zip(a).map(changed_fields).unwrap_or_default(),
+                before: b.cloned(),
+                after: a.cloned(),
+            })
+        })
+        .collect()
+}
+
+fn changed_fields((before, after): (&Value, &Value)) -> Vec<String> {
+    match (before, after) {
+        (Value::Object(b), Value::Object(a)) => b
+            .keys()
+            .chain(a.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter(|key| b.get(*key) != a.get(*key))
+            .cloned()
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Every comparable object of an `export_metadata` document by path
+fn objects(metadata: &Value) -> BTreeMap<Vec<String>, Value> {
+    let mut objects = BTreeMap::new();
+    for (key, value) in metadata.as_object().into_iter().flatten() {
+        match (key.as_str(), value) {
+            ("sources", _) => {}
+            (_, Value::Array(items)) if items.iter().all(|i| i["name"].is_string()) => {
+                for item in items {
+                    let name = item["name"].as_str().unwrap_or_default();
+                    objects.insert(vec![key.clone(), name.to_string()], item.clone());
+                }
+            }
+            _ => {
+                objects.insert(vec![key.clone()], value.clone());
+            }
+        }
+    }
+    for source in items(&metadata["sources"]) {
+        let mut source = source.as_object().cloned().unwrap_or_default();
+        let tables = source.remove("tables").unwrap_or_default();
+        let functions = source.remove("functions").unwrap_or_default();
+        let name = source
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let source_path = vec![format!("source {name}")];
+        for table in items(&tables) {
+            let mut table = table.as_object().cloned().unwrap_or_default();
+            let table_path = child(
+                &source_path,
+                format!("table {}", qualified_name(&table["table"])),
+            );
+            for (key, label, id) in TABLE_ITEMS {
+                for item in items(&table.remove(key).unwrap_or_default()) {
+                    let id = item[id].as_str().unwrap_or_default();
+                    objects.insert(child(&table_path, format!("{label} {id}")), item.clone());
+                }
+            }
+            objects.insert(table_path, Value::Object(table));
+        }
+        for function in items(&functions) {
+            let label = format!("function {}", qualified_name(&function["function"]));
+            objects.insert(child(&source_path, label), function.clone());
+        }
+        objects.insert(source_path, Value::Object(source));
+    }
+    objects
+}
+
+fn items(value: &Value) -> &[Value] {
+    value.as_array().map(Vec::as_slice).unwrap_or_default()
+}
+
+fn child(path: &[String], name: String) -> Vec<String> {
+    path.iter().cloned().chain([name]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn metadata(permission_filter: Value, relationships: Value) -> Value {
+        json!({
+            "version": 3,
+            "sources": [{
+                "name": "default",
+                "kind": "postgres",
+                "tables": [{
+                    "table": { "schema": "public", "name": "orders" },
+                    "object_relationships": relationships,
+                    "select_permissions": [
+                        { "role": "user", "permission": { "columns": ["id"], "filter": permission_filter } }
+                    ]
+                }]
+            }],
+            "actions": [{ "name": "login" }]
+        })
+    }
+
+    #[test]
+    fn identical_metadata_has_no_differences() {
+        let m = metadata(json!({}), json!([]));
+        assert!(diff(&m, &m).is_empty());
+    }
+
+    #[test]
+    fn diff_groups_changes_by_object() {
+        let before = metadata(
+            json!({}),
+            json!([{ "name": "user", "using": { "foreign_key_constraint_on": "user_id" } }]),
+        );
+        let after = metadata(
+            json!({ "user_id": { "_eq": "X-Hasura-User-Id" } }),
+            json!([{ "name": "buyer", "using": { "foreign_key_constraint_on": "user_id" } }]),
+        );
+        let entries = diff(&before, &after);
+        let summary = entries
+            .iter()
+            .map(|e| (e.change, e.object.join(" / "), e.fields.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (
+                    Change::Added,
+                    "source default / table public.orders / object relationship buyer".to_string(),
+                    vec![]
+                ),
+                (
+                    Change::Removed,
+                    "source default / table public.orders / object relationship user".to_string(),
+                    vec![]
+                ),
+                (
+                    Change::Changed,
+                    "source default / table public.orders / select permission user".to_string(),
+                    vec!["permission".to_string()]
+                ),
+            ]
+        );
+    }
+}
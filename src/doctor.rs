@@ -0,0 +1,145 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::driver::Driver;
+use crate::metadata::MetadataSource;
+use crate::util::{HGEHealth, HasuraUtils};
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DoctorCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl DoctorCheck {
+    fn new(name: impl Into<String>, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl Display for CheckStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckStatus::Pass => write!(f, "pass"),
+            CheckStatus::Warn => write!(f, "warn"),
+            CheckStatus::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+impl HasuraUtils {
+    /// Runs every health and consistency check against the instance.
+    /// Failing requests are reported as failed checks instead of errors.
+    pub async fn doctor(&self) -> Vec<DoctorCheck> {
+        let mut checks = vec![];
+
+        checks.push(match self.check_health().await {
+            Ok(HGEHealth::Ok) => DoctorCheck::new("healthz", CheckStatus::Pass, "OK"),
+            Ok(HGEHealth::Inconsistent) => DoctorCheck::new(
+                "healthz",
+                CheckStatus::Warn,
+                "server reports inconsistent metadata",
+            ),
+            Ok(HGEHealth::Error) => DoctorCheck::new("healthz", CheckStatus::Fail, "ERROR"),
+            Err(err) => DoctorCheck::new("healthz", CheckStatus::Fail, err.to_string()),
+        });
+
+        checks.push(match self.get_version().await {
+            Ok(v) => DoctorCheck::new("version", CheckStatus::Pass, v.version),
+            Err(err) => DoctorCheck::new("version", CheckStatus::Fail, err.to_string()),
+        });
+
+        checks.push(match self.get_inconsistent_metadata().await {
+            Ok(res) if res.is_consistent => {
+                DoctorCheck::new("metadata consistency", CheckStatus::Pass, "consistent")
+            }
+            Ok(res) => DoctorCheck::new(
+                "metadata consistency",
+                CheckStatus::Warn,
+                format!("{} inconsistent object(s)", res.inconsistent_objects.len()),
+            ),
+            Err(err) => {
+                DoctorCheck::new("metadata consistency", CheckStatus::Fail, err.to_string())
+            }
+        });
+
+        let metadata = match self.get_metadata().await {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                checks.push(DoctorCheck::new(
+                    "metadata export",
+                    CheckStatus::Fail,
+                    err.to_string(),
+                ));
+                return checks;
+            }
+        };
+        checks.push(DoctorCheck::new(
+            "metadata export",
+            CheckStatus::Pass,
+            format!(
+                "version {}, {} source(s)",
+                metadata.version(),
+                metadata.sources().len()
+            ),
+        ));
+
+        let driver = self.env.driver;
+        for configured in &self.env.sources {
+            let source = metadata.sources().iter().find(|s| &s.name == configured);
+            checks.push(match source {
+                None => DoctorCheck::new(
+                    "configured source",
+                    CheckStatus::Fail,
+                    format!("source {configured} is not in the metadata"),
+                ),
+                Some(source) if Driver::from_kind(source.kind()).is_none() => DoctorCheck::new(
+                    "configured source",
+                    CheckStatus::Warn,
+                    format!(
+                        "source {configured} is {}, which is not supported, using driver {driver}",
+                        source.kind()
+                    ),
+                ),
+                Some(source) => DoctorCheck::new(
+                    "configured source",
+                    CheckStatus::Pass,
+                    format!("{configured} ({})", source.driver(driver)),
+                ),
+            });
+        }
+
+        checks.extend(self.ping_sources(metadata.sources()).await);
+
+        checks
+    }
+
+    /// Runs `SELECT 1` on every source
+    pub async fn ping_sources(&self, sources: &[MetadataSource]) -> Vec<DoctorCheck> {
+        let mut checks = vec![];
+        for source in sources {
+            let name = format!("source {}", source.name);
+            let kind = source.kind();
+            let driver = source.driver(self.env.driver);
+            checks.push(match self.ping_source(&source.name, driver).await {
+                Ok(()) => DoctorCheck::new(name, CheckStatus::Pass, format!("{kind}, SELECT 1")),
+                Err(err) => DoctorCheck::new(name, CheckStatus::Fail, err.to_string()),
+            });
+        }
+        checks
+    }
+}
@@ -0,0 +1,113 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::error::HasuraUtilsError;
+
+/// Database backend of a source, deciding the prefix of the metadata API
+/// requests and the SQL used to introspect the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Driver {
+    Pg,
+    Citus,
+    Cockroach,
+    Mssql,
+}
+
+impl Driver {
+    pub const ALL: &'static [Driver] =
+        &[Driver::Pg, Driver::Citus, Driver::Cockroach, Driver::Mssql];
+
+    /// Prefix of the metadata API requests, e.g. `pg` in `pg_track_table`
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Driver::Pg => "pg",
+            Driver::Citus => "citus",
+            Driver::Cockroach => "cockroach",
+            Driver::Mssql => "mssql",
+        }
+    }
+
+    /// Source `kind` in the metadata
+    pub fn kind(self) -> &'static str {
+        match self {
+            Driver::Pg => "postgres",
+            Driver::Citus => "citus",
+            Driver::Cockroach => "cockroach",
+            Driver::Mssql => "mssql",
+        }
+    }
+
+    /// Driver of a source `kind` of the metadata
+    pub fn from_kind(kind: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|d| d.kind() == kind)
+    }
+
+    /// `from_kind` for a source the command runs against, failing on kinds no
+    /// driver supports, e.g. `bigquery`
+    pub fn require_kind(kind: &str) -> Result<Self, HasuraUtilsError> {
+        Self::from_kind(kind)
+            .ok_or_else(|| HasuraUtilsError::InvalidConfig(format!("unsupported kind {kind}")))
+    }
+
+    /// `<prefix>_<request>`, e.g. `mssql_track_table` for `track_table`
+    pub fn request_type(self, request: &str) -> String {
+        format!("{}_{request}", self.prefix())
+    }
+
+    /// Type of the `/v2/query` request running SQL, which has no prefix for Postgres
+    pub fn run_sql_type(self) -> &'static str {
+        match self {
+            Driver::Pg => "run_sql",
+            Driver::Citus => "citus_run_sql",
+            Driver::Cockroach => "cockroach_run_sql",
+            Driver::Mssql => "mssql_run_sql",
+        }
+    }
+
+    /// Whether Hasura can track functions of this backend
+    pub fn supports_functions(self) -> bool {
+        match self {
+            Driver::Pg | Driver::Citus => true,
+            Driver::Cockroach | Driver::Mssql => false,
+        }
+    }
+}
+
+impl Display for Driver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.prefix())
+    }
+}
+
+impl FromStr for Driver {
+    type Err = HasuraUtilsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|d| d.prefix() == s)
+            .ok_or_else(|| {
+                HasuraUtilsError::InvalidConfig(format!("{s} driver is not supported yet"))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_requests_with_the_driver() {
+        let driver: Driver = "mssql".parse().unwrap();
+        assert_eq!(driver.request_type("track_table"), "mssql_track_table");
+        assert_eq!(driver.run_sql_type(), "mssql_run_sql");
+        assert_eq!(Driver::Pg.run_sql_type(), "run_sql");
+        assert_eq!(Driver::from_kind("postgres"), Some(Driver::Pg));
+        assert_eq!(Driver::from_kind("cockroach"), Some(Driver::Cockroach));
+        assert_eq!(
+            Driver::Citus.request_type("create_object_relationship"),
+            "citus_create_object_relationship"
+        );
+        assert!("mysql".parse::<Driver>().is_err());
+    }
+}
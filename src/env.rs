@@ -2,18 +2,29 @@ extern crate dotenv;
 use reqwest::{header, Client};
 
 use dotenv::dotenv;
+
+use crate::cli::ConfigArgs;
+use crate::config::ConfigFile;
+use crate::driver::Driver;
+use crate::error::HasuraUtilsError;
 use serde::{Deserialize, Serialize};
 use std::env;
 
-const SUPPORTED_DRIVERS: [&'static str; 1] = ["pg"];
-
+#[derive(Clone)]
 pub struct EnvVars {
+    /// Source the commands run against, see `HasuraUtils::with_source`
     pub source: String,
-    pub driver: String,
+    /// Sources selected with `--source`, the env var or the profile. Only commands
+    /// running against sources require one, see `HasuraUtils::target_sources`.
+    pub sources: Vec<String>,
+    /// Whether `--all-sources` selects every source of the metadata instead
+    pub all_sources: bool,
+    pub driver: Driver,
     pub metadata_url: String,
     pub query_url: String,
     pub admin_secret: String,
     pub healthz: String,
+    pub version_url: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -30,15 +41,6 @@ struct RunSQLArgs<'a> {
     read_only: bool,
 }
 
-impl<'a> Default for RunSQL<'a> {
-    fn default() -> Self {
-        Self {
-            r#type: "run_sql",
-            args: Default::default(),
-        }
-    }
-}
-
 impl<'a> Default for RunSQLArgs<'a> {
     fn default() -> Self {
         Self {
@@ -51,48 +53,281 @@ impl<'a> Default for RunSQLArgs<'a> {
 }
 
 impl EnvVars {
-    pub fn init() -> Self {
+    /// Resolves every setting from, in order of precedence, the command line flags,
+    /// the env vars and the selected profile of the config file
+    pub fn init(args: &ConfigArgs) -> Result<Self, HasuraUtilsError> {
         dotenv().ok();
-        let source =
-            env::var("HASURA_UTILS_SOURCE").expect("HASURA_UTILS_SOURCE env var is not set");
-        let driver = env::var("HASURA_UTILS_DDRIVER").unwrap_or("pg".to_string());
-        let data_url =
-            env::var("HASURA_DATA_API_URL").expect("HASURA_DATA_API_URL env var is not set");
-        let admin_secret =
-            env::var("HASURA_ADMIN_SECRET").expect("HASURA_ADMIN_SECRET env var is not set");
-        if !SUPPORTED_DRIVERS.contains(&&driver[..]) {
-            panic!("{driver} driver is not supported yet");
-        }
-        Self {
-            source,
+        let config = ConfigFile::load(args.config.as_deref())?;
+        Self::resolve(args, &config, |name| env::var(name).ok())
+    }
+
+    /// `init` with the env vars read through `var`
+    fn resolve(
+        args: &ConfigArgs,
+        config: &ConfigFile,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, HasuraUtilsError> {
+        let profile_name = args.profile.clone().or_else(|| var("HASURA_UTILS_PROFILE"));
+        let profile = config.profile(profile_name.as_deref())?;
+        let sources = match &args.source[..] {
+            [] => var("HASURA_UTILS_SOURCE")
+                .or(profile.source.clone())
+                .into_iter()
+                .collect(),
+            sources => sources.to_vec(),
+        };
+        let driver = args
+            .driver
+            .clone()
+            .or_else(|| var("HASURA_UTILS_DRIVER"))
+            .or_else(|| var("HASURA_UTILS_DDRIVER"))
+            .or(profile.driver.clone())
+            .unwrap_or_else(|| "pg".to_string());
+        let data_url = args
+            .endpoint
+            .clone()
+            .or_else(|| var("HASURA_DATA_API_URL"))
+            .or(profile.endpoint.clone())
+            .ok_or(HasuraUtilsError::MissingConfig(
+                "endpoint is not set, use --endpoint, HASURA_DATA_API_URL or a profile",
+            ))?;
+        let data_url = data_url.trim_end_matches('/');
+        let admin_secret = match args
+            .admin_secret
+            .clone()
+            .or_else(|| var("HASURA_ADMIN_SECRET"))
+        {
+            Some(secret) => secret,
+            None => profile
+                .admin_secret()?
+                .ok_or(HasuraUtilsError::MissingConfig(
+                    "admin secret is not set, use --admin-secret, HASURA_ADMIN_SECRET or a profile",
+                ))?,
+        };
+        let mut env = Self::new(sources, driver, data_url, admin_secret)?;
+        env.all_sources = args.all_sources;
+        Ok(env)
+    }
+
+    /// Settings of the named profile alone, ignoring flags and env vars,
+    /// for talking to a second Hasura instance
+    pub fn for_profile(config: Option<&str>, name: &str) -> Result<Self, HasuraUtilsError> {
+        let profile = ConfigFile::load(config)?.profile(Some(name))?;
+        let data_url = profile
+            .endpoint
+            .clone()
+            .ok_or(HasuraUtilsError::MissingConfig(
+                "endpoint is not set in the profile",
+            ))?;
+        let admin_secret = profile
+            .admin_secret()?
+            .ok_or(HasuraUtilsError::MissingConfig(
+                "admin secret is not set in the profile",
+            ))?;
+        Self::new(
+            vec![profile.source.unwrap_or_else(|| "default".to_string())],
+            profile.driver.unwrap_or_else(|| "pg".to_string()),
+            data_url.trim_end_matches('/'),
+            admin_secret,
+        )
+    }
+
+    pub(crate) fn new(
+        sources: Vec<String>,
+        driver: String,
+        data_url: &str,
+        admin_secret: String,
+    ) -> Result<Self, HasuraUtilsError> {
+        let driver = driver.parse()?;
+        Ok(Self {
+            source: sources.first().cloned().unwrap_or_default(),
+            sources,
+            all_sources: false,
             driver,
             admin_secret,
             query_url: format!("{data_url}/v2/query"),
             metadata_url: format!("{data_url}/v1/metadata"),
             healthz: format!("{data_url}/healthz"),
+            version_url: format!("{data_url}/v1/version"),
+        })
+    }
+
+    /// Settings of a `pg` endpoint selecting `sources`, for tests
+    #[cfg(test)]
+    pub(crate) fn for_test(sources: &[&str], all_sources: bool) -> Self {
+        let sources = sources.iter().map(|s| s.to_string()).collect();
+        let mut env = Self::new(sources, "pg".to_string(), "http://hasura", String::new()).unwrap();
+        env.all_sources = all_sources;
+        env
+    }
+
+    /// Whether the command line selects several sources, with `--all-sources` or a
+    /// repeated `--source`, even if they resolve to a single one
+    pub fn selects_many_sources(&self) -> bool {
+        self.all_sources || self.sources.len() > 1
+    }
+
+    /// Fails unless the command line, the env var or the profile selects a source
+    pub fn require_sources(&self) -> Result<(), HasuraUtilsError> {
+        match self.sources.is_empty() && !self.all_sources {
+            true => Err(HasuraUtilsError::MissingConfig(
+                "source is not set, use --source, --all-sources, HASURA_UTILS_SOURCE or a profile",
+            )),
+            false => Ok(()),
         }
     }
 
-    pub fn make_client(&self) -> Client {
+    pub fn make_client(&self) -> Result<Client, HasuraUtilsError> {
         let mut headers = header::HeaderMap::new();
         headers.insert(
             "X-HASURA-ADMIN-SECRET",
-            header::HeaderValue::from_str(&self.admin_secret).unwrap(),
+            header::HeaderValue::from_str(&self.admin_secret)?,
         );
-        return Client::builder()
-            .default_headers(headers)
-            .build()
-            .expect("unable to construct client");
+        let client = Client::builder().default_headers(headers).build()?;
+        Ok(client)
     }
 
     pub fn get_run_sql<'a>(&'a self, sql: &'a str) -> RunSQL<'a> {
+        self.get_source_run_sql(self.driver, &self.source, sql)
+    }
+
+    pub fn get_source_run_sql<'a>(
+        &self,
+        driver: Driver,
+        source: &'a str,
+        sql: &'a str,
+    ) -> RunSQL<'a> {
         RunSQL {
+            r#type: driver.run_sql_type(),
             args: RunSQLArgs {
-                source: &self.source,
+                source,
                 sql,
                 ..Default::default()
             },
-            ..Default::default()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use clap::StructOpt;
+
+    use super::*;
+    use crate::cli::App;
+
+    fn args(flags: &[&str]) -> ConfigArgs {
+        let cli = ["hasura_utils"]
+            .iter()
+            .chain(flags)
+            .chain(&["source", "list"]);
+        App::try_parse_from(cli).unwrap().config
+    }
+
+    fn config() -> ConfigFile {
+        toml::from_str(
+            r#"
+            default_profile = "dev"
+
+            [profiles.dev]
+            endpoint = "http://localhost:8080"
+            admin_secret = "dev-secret"
+            source = "default"
+
+            [profiles.prod]
+            endpoint = "https://hasura.example.com/"
+            admin_secret = "prod-secret"
+            source = "warehouse"
+            driver = "mssql"
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn resolve(flags: &[&str], vars: &[(&str, &str)]) -> Result<EnvVars, HasuraUtilsError> {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        EnvVars::resolve(&args(flags), &config(), |name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn uses_the_default_profile_without_flags_or_env_vars() {
+        let env = resolve(&[], &[]).unwrap();
+        assert_eq!(env.metadata_url, "http://localhost:8080/v1/metadata");
+        assert_eq!(env.admin_secret, "dev-secret");
+        assert_eq!(env.sources, ["default"]);
+        assert_eq!(env.driver, Driver::Pg);
+    }
+
+    #[test]
+    fn env_vars_take_precedence_over_the_profile() {
+        let env = resolve(
+            &[],
+            &[
+                ("HASURA_UTILS_PROFILE", "prod"),
+                ("HASURA_UTILS_SOURCE", "reporting"),
+                ("HASURA_ADMIN_SECRET", "env-secret"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(env.metadata_url, "https://hasura.example.com/v1/metadata");
+        assert_eq!(env.admin_secret, "env-secret");
+        assert_eq!(env.sources, ["reporting"]);
+        assert_eq!(env.driver, Driver::Mssql);
+    }
+
+    #[test]
+    fn flags_take_precedence_over_env_vars() {
+        let env = resolve(
+            &[
+                "--profile",
+                "dev",
+                "--endpoint",
+                "http://flag:8080",
+                "--source",
+                "a",
+                "--source",
+                "b",
+                "--driver",
+                "citus",
+            ],
+            &[
+                ("HASURA_UTILS_PROFILE", "prod"),
+                ("HASURA_DATA_API_URL", "http://env:8080"),
+                ("HASURA_UTILS_SOURCE", "reporting"),
+                ("HASURA_UTILS_DRIVER", "cockroach"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(env.metadata_url, "http://flag:8080/v1/metadata");
+        assert_eq!(env.admin_secret, "dev-secret");
+        assert_eq!(env.sources, ["a", "b"]);
+        assert_eq!(env.driver, Driver::Citus);
+    }
+
+    #[test]
+    fn unknown_profile_is_a_config_error() {
+        let err = resolve(&["--profile", "staging"], &[]).err().unwrap();
+        assert_eq!(err.exit_code(), 78);
+    }
+
+    #[test]
+    fn selects_many_sources_from_the_command_line() {
+        assert!(!EnvVars::for_test(&["default"], false).selects_many_sources());
+        assert!(EnvVars::for_test(&["default", "reporting"], false).selects_many_sources());
+        assert!(EnvVars::for_test(&[], true).selects_many_sources());
+    }
+
+    #[test]
+    fn commands_on_sources_require_one() {
+        assert!(EnvVars::for_test(&["default"], false)
+            .require_sources()
+            .is_ok());
+        assert!(EnvVars::for_test(&[], true).require_sources().is_ok());
+        let err = EnvVars::for_test(&[], false).require_sources().unwrap_err();
+        assert_eq!(err.exit_code(), 78);
+    }
+}
@@ -1,10 +1,83 @@
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::Display, path::PathBuf};
+
+use reqwest::header::InvalidHeaderValue;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Debug)]
 pub enum HasuraUtilsError {
     Other(&'static str),
     Parse(serde_json::Error),
+    Yaml(serde_yaml::Error),
+    Io(PathBuf, std::io::Error),
     Request(reqwest::Error),
+    MissingConfig(&'static str),
+    InvalidConfig(String),
+    InvalidHeader(InvalidHeaderValue),
+    AlreadyTracked(String),
+    HasuraApi(Box<HasuraApiError>),
+}
+
+/// Error body returned by the Hasura metadata and query APIs
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HasuraApiError {
+    pub code: String,
+    pub error: String,
+    pub path: String,
+    #[serde(default)]
+    pub internal: Option<Value>,
+    /// Path of the API endpoint that failed, e.g. `/v1/metadata`
+    #[serde(skip_deserializing)]
+    pub endpoint: String,
+    /// Failing item of a `bulk` request
+    #[serde(skip_deserializing)]
+    pub request: Option<Value>,
+}
+
+impl HasuraApiError {
+    /// Index of the failing item when `path` points into a `bulk` request (`$.args[2].args`)
+    pub fn bulk_index(&self) -> Option<usize> {
+        let rest = self.path.strip_prefix("$.args[")?;
+        rest[..rest.find(']')?].parse().ok()
+    }
+}
+
+impl Display for HasuraApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} (code: {}, path: {})",
+            self.endpoint, self.error, self.code, self.path
+        )?;
+        if let Some(request) = &self.request {
+            write!(f, "\n  request: {request}")?;
+        }
+        if let Some(internal) = &self.internal {
+            write!(f, "\n  internal: {internal}")?;
+        }
+        Ok(())
+    }
+}
+
+impl HasuraUtilsError {
+    /// Process exit code reported by `main` for this error:
+    /// 1 for generic failures, 3 for objects that are already tracked,
+    /// 4 for failed requests, 5 for errors returned by the Hasura API,
+    /// 74 (`EX_IOERR`) for file system errors and 78 (`EX_CONFIG`) for configuration errors
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            HasuraUtilsError::Other(_) | HasuraUtilsError::Parse(_) | HasuraUtilsError::Yaml(_) => {
+                1
+            }
+            HasuraUtilsError::AlreadyTracked(_) => 3,
+            HasuraUtilsError::Request(_) => 4,
+            HasuraUtilsError::HasuraApi(_) => 5,
+            HasuraUtilsError::Io(..) => 74,
+            HasuraUtilsError::MissingConfig(_)
+            | HasuraUtilsError::InvalidConfig(_)
+            | HasuraUtilsError::InvalidHeader(_) => 78,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -15,7 +88,16 @@ impl Display for HasuraUtilsError {
         match &self {
             HasuraUtilsError::Other(err) => write!(f, "{err}"),
             HasuraUtilsError::Parse(parse_error) => parse_error.fmt(f),
+            HasuraUtilsError::Yaml(yaml_error) => yaml_error.fmt(f),
+            HasuraUtilsError::Io(path, io_error) => write!(f, "{}: {io_error}", path.display()),
             HasuraUtilsError::Request(request_error) => request_error.fmt(f),
+            HasuraUtilsError::MissingConfig(err) => write!(f, "{err}"),
+            HasuraUtilsError::InvalidConfig(err) => write!(f, "{err}"),
+            HasuraUtilsError::InvalidHeader(_) => {
+                write!(f, "admin secret is not a valid header value")
+            }
+            HasuraUtilsError::AlreadyTracked(object) => write!(f, "{object} is already tracked"),
+            HasuraUtilsError::HasuraApi(api_error) => api_error.fmt(f),
         }
     }
 }
@@ -25,7 +107,14 @@ impl Error for HasuraUtilsError {
         match *self {
             HasuraUtilsError::Other(_) => None,
             HasuraUtilsError::Parse(ref e) => Some(e),
+            HasuraUtilsError::Yaml(ref e) => Some(e),
+            HasuraUtilsError::Io(_, ref e) => Some(e),
             HasuraUtilsError::Request(ref e) => Some(e),
+            HasuraUtilsError::InvalidHeader(ref e) => Some(e),
+            HasuraUtilsError::MissingConfig(_)
+            | HasuraUtilsError::InvalidConfig(_)
+            | HasuraUtilsError::AlreadyTracked(_)
+            | HasuraUtilsError::HasuraApi(_) => None,
         }
     }
 }
@@ -36,12 +125,24 @@ impl From<serde_json::Error> for HasuraUtilsError {
     }
 }
 
+impl From<serde_yaml::Error> for HasuraUtilsError {
+    fn from(err: serde_yaml::Error) -> Self {
+        HasuraUtilsError::Yaml(err)
+    }
+}
+
 impl From<reqwest::Error> for HasuraUtilsError {
     fn from(err: reqwest::Error) -> Self {
         HasuraUtilsError::Request(err)
     }
 }
 
+impl From<InvalidHeaderValue> for HasuraUtilsError {
+    fn from(err: InvalidHeaderValue) -> Self {
+        HasuraUtilsError::InvalidHeader(err)
+    }
+}
+
 impl From<OtherError> for HasuraUtilsError {
     fn from(err: OtherError) -> Self {
         HasuraUtilsError::Other(err.0)
@@ -0,0 +1,52 @@
+use wildmatch::WildMatch;
+
+use crate::metadata::QualifiedTable;
+
+/// A `schema.table` glob such as `archive.*` or `*.tmp_*`.
+/// A pattern without a schema part matches the name in every schema.
+#[derive(Debug)]
+pub struct ObjectPattern {
+    schema: WildMatch,
+    name: WildMatch,
+}
+
+impl ObjectPattern {
+    pub fn new(pattern: &str) -> Self {
+        let (schema, name) = pattern.split_once('.').unwrap_or(("*", pattern));
+        Self {
+            schema: WildMatch::new(schema),
+            name: WildMatch::new(name),
+        }
+    }
+
+    pub fn matches(&self, object: &QualifiedTable) -> bool {
+        self.schema.matches(&object.schema) && self.name.matches(&object.name)
+    }
+}
+
+/// Selection of tables, relationships and functions by `--schema`, `--include`
+/// and `--exclude`. The default filter selects everything.
+#[derive(Debug, Default)]
+pub struct ObjectFilter {
+    schema: Option<String>,
+    include: Vec<ObjectPattern>,
+    exclude: Vec<ObjectPattern>,
+}
+
+impl ObjectFilter {
+    pub fn new(schema: Option<String>, include: &[String], exclude: &[String]) -> Self {
+        Self {
+            schema,
+            include: include.iter().map(|p| ObjectPattern::new(p)).collect(),
+            exclude: exclude.iter().map(|p| ObjectPattern::new(p)).collect(),
+        }
+    }
+
+    /// Whether `object` is in the schema, matches an include pattern (if any)
+    /// and matches no exclude pattern
+    pub fn matches(&self, object: &QualifiedTable) -> bool {
+        self.schema.as_ref().is_none_or(|s| s == &object.schema)
+            && (self.include.is_empty() || self.include.iter().any(|p| p.matches(object)))
+            && !self.exclude.iter().any(|p| p.matches(object))
+    }
+}
@@ -1,24 +1,68 @@
 mod cli;
+mod config;
+mod diff;
+mod doctor;
+mod driver;
 mod env;
 mod error;
+mod filter;
 mod metadata;
+mod metadata_dir;
+mod naming;
+mod output;
+mod source;
 mod sql;
+mod sync;
 mod types;
 mod util;
 
 use clap::StructOpt;
-use cli::{App, Commands};
+use cli::{App, Commands, MetadataCommands, SourceCommands};
+use diff::{DiffTarget, DRIFT_EXIT_CODE};
+use doctor::CheckStatus;
 use env::EnvVars;
+use filter::ObjectFilter;
+use metadata_dir::MetadataDir;
 use util::HasuraUtils;
 
-use crate::metadata::QualifiedTable;
+use crate::metadata::{QualifiedFunction, QualifiedTable};
 
 #[tokio::main]
 async fn main() {
     let cli = App::parse();
-    let env = EnvVars::init();
-    let client = env.make_client();
-    let app = HasuraUtils { client, env };
+    let output = cli.output;
+    let env = EnvVars::init(&cli.config).unwrap_or_else(|err| output.exit_with(&err));
+    let client = env
+        .make_client()
+        .unwrap_or_else(|err| output.exit_with(&err));
+    let app = HasuraUtils {
+        client,
+        env,
+        dry_run: cli.dry_run,
+    };
+
+    // Runs `$run` with `$app` bound to a copy of `app` for each target source,
+    // then prints the results per source. Sources without a driver fail alone.
+    macro_rules! per_source {
+        ($app:ident => $run:expr) => {{
+            let sources = app
+                .target_sources()
+                .await
+                .unwrap_or_else(|err| output.exit_with(&err));
+            let mut results = vec![];
+            for (source, driver) in sources {
+                let result = match driver {
+                    Ok(driver) => {
+                        let $app = app.with_source(&source, driver);
+                        $run.await
+                    }
+                    Err(err) => Err(err),
+                };
+                results.push((source, result));
+            }
+            output.print_per_source(&results, app.env.selects_many_sources());
+        }};
+    }
 
     match &cli.command {
         Commands::TrackTable {
@@ -27,37 +71,172 @@ async fn main() {
             all,
             ignore,
             list,
+            kinds,
+            include,
+            exclude,
         } => {
+            let filter = ObjectFilter::new(schema.clone(), include, exclude);
             if *all {
-                let res = app.track_all_tables(ignore).await;
-                println!("{res:?}");
+                per_source!(app => app.track_all_tables(
+                    ignore.as_deref().unwrap_or_default(),
+                    kinds,
+                    &filter
+                ));
             } else if *list {
-                let res = app.get_all_tables().await;
-                println!("{res:?}");
+                per_source!(app => app.get_all_tables(kinds, &filter));
             } else {
-                let res = app
-                    .track_table(QualifiedTable {
+                per_source!(app => app.track_table(QualifiedTable {
+                    name: name.as_ref().unwrap().to_string(),
+                    schema: schema.as_ref().unwrap().to_string(),
+                }));
+            }
+        }
+        Commands::UntrackTable {
+            name,
+            schema,
+            all_in_schema,
+            cascade,
+        } => {
+            if let Some(schema) = all_in_schema {
+                per_source!(app => app.untrack_schema_tables(schema, *cascade));
+            } else {
+                per_source!(app => app.untrack_table(
+                    QualifiedTable {
                         name: name.as_ref().unwrap().to_string(),
                         schema: schema.as_ref().unwrap().to_string(),
-                    })
-                    .await;
-                println!("{res:?}");
+                    },
+                    *cascade,
+                ));
             }
         }
         Commands::TrackRel {
-            name: _,
-            schema: _,
+            name,
+            schema,
             all,
+            include,
+            exclude,
         } => {
             if *all {
-                let res = app.track_all_relationships().await;
-                println!("\n{res:?}");
+                let filter = ObjectFilter::new(schema.clone(), include, exclude);
+                per_source!(app => app.track_all_relationships(&filter));
             } else {
-                println!("Not implemented yet!");
+                per_source!(app => app.track_relationships(QualifiedTable {
+                    name: name.as_ref().unwrap().to_string(),
+                    schema: schema.as_ref().unwrap().to_string(),
+                }));
             }
         }
-        _ => {
-            println!("Sorry this functionality is not supported yet!")
+        Commands::TrackFn {
+            name,
+            schema,
+            all,
+            include,
+            exclude,
+        } => {
+            if *all {
+                let filter = ObjectFilter::new(schema.clone(), include, exclude);
+                per_source!(app => app.track_all_functions(&filter));
+            } else {
+                per_source!(app => app.track_function(QualifiedFunction {
+                    name: name.as_ref().unwrap().to_string(),
+                    schema: schema.as_ref().unwrap().to_string(),
+                }));
+            }
+        }
+        Commands::PruneRels { cascade } => {
+            per_source!(app => app.prune_relationships(*cascade));
+        }
+        Commands::Sync {
+            schema,
+            cascade,
+            kinds,
+            include,
+            exclude,
+        } => {
+            let filter = ObjectFilter::new(schema.clone(), include, exclude);
+            per_source!(app => app.sync(kinds, &filter, *cascade));
         }
+        Commands::Doctor { strict } => {
+            let checks = app.doctor().await;
+            let failed = checks.iter().any(|c| {
+                c.status == CheckStatus::Fail || (*strict && c.status == CheckStatus::Warn)
+            });
+            output.print(&Ok(checks));
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Commands::Metadata { command } => match command {
+            MetadataCommands::Export { dir, format } => {
+                let res = app
+                    .export_metadata()
+                    .await
+                    .and_then(|metadata| MetadataDir::new(dir, *format).write(&metadata));
+                output.print(&res);
+            }
+            MetadataCommands::Apply {
+                dir,
+                format,
+                allow_inconsistent_metadata,
+            } => {
+                let metadata = MetadataDir::new(dir, *format)
+                    .read()
+                    .unwrap_or_else(|err| output.exit_with(&err));
+                let res = app
+                    .replace_metadata(&metadata, *allow_inconsistent_metadata)
+                    .await;
+                output.print(&res);
+            }
+            MetadataCommands::Diff {
+                dir,
+                format,
+                against_profile,
+            } => {
+                let target = match (dir, against_profile) {
+                    (Some(dir), _) => DiffTarget::Dir(MetadataDir::new(dir, *format)),
+                    (None, profile) => DiffTarget::Profile {
+                        config: cli.config.config.as_deref(),
+                        name: profile.as_deref().unwrap_or_default(),
+                    },
+                };
+                let res = app.diff_metadata(target).await;
+                let drifted = res.as_ref().is_ok_and(|entries| !entries.is_empty());
+                output.print(&res);
+                if drifted {
+                    std::process::exit(DRIFT_EXIT_CODE);
+                }
+            }
+            MetadataCommands::Inconsistencies { drop, reload } => {
+                if *drop {
+                    output.print(&app.drop_inconsistent_metadata().await);
+                } else if *reload {
+                    output.print(&app.reload_inconsistent_metadata().await);
+                } else {
+                    output.print(&app.get_inconsistent_objects().await);
+                }
+            }
+        },
+        Commands::Source { command } => match command {
+            SourceCommands::Add { name, connection } => {
+                output.print(&app.add_source(name, connection).await);
+            }
+            SourceCommands::Update { name, connection } => {
+                output.print(&app.update_source(name, connection).await);
+            }
+            SourceCommands::Remove { name, cascade } => {
+                output.print(&app.remove_source(name, *cascade).await);
+            }
+            SourceCommands::List => output.print(&app.list_sources().await),
+            SourceCommands::Test => {
+                let res = app.test_sources().await;
+                let failed = res
+                    .as_ref()
+                    .is_ok_and(|checks| checks.iter().any(|c| c.status == CheckStatus::Fail));
+                output.print(&res);
+                if failed {
+                    std::process::exit(1);
+                }
+            }
+        },
     }
 }
@@ -1,59 +1,179 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Display;
+
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
-use crate::types::{
-    CreateArrayRelationship, CreateObjectRelationship, CreateRelationship, RelType,
-    SQLFKRelationship,
-};
+use crate::driver::Driver;
+use crate::naming::resolve_relationship_names;
+use crate::types::{CreateRelationship, RelType, SQLFKRelationship, SQLFunction, SQLTable};
 
-#[derive(Deserialize, Debug)]
+/// The `export_metadata` document (metadata v3). Every object Hasura exports is
+/// modelled so that a document deserializes and reserializes without losing fields,
+/// and each struct keeps the fields it does not model (e.g. `native_queries` of newer
+/// servers) in `extra`. Empty lists are omitted like Hasura does, except for `sources`
+/// and `tables`.
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Metadata {
     version: u8,
     sources: Vec<MetadataSource>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    remote_schemas: Vec<RemoteSchema>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    query_collections: Vec<QueryCollection>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allowlist: Vec<AllowlistEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    actions: Vec<Action>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    custom_types: Option<CustomTypes>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    cron_triggers: Vec<CronTrigger>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    rest_endpoints: Vec<RestEndpoint>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    inherited_roles: Vec<InheritedRole>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    api_limits: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    graphql_schema_introspection: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    network: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    backend_configs: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metrics_config: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    opentelemetry: Option<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
 }
 
 impl Metadata {
-    pub fn get_untracked_tables(&self, tables: Vec<QualifiedTable>) -> Vec<QualifiedTable> {
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn sources(&self) -> &[MetadataSource] {
+        &self.sources
+    }
+
+    /// Tables that are not tracked in `source`
+    pub fn get_untracked_tables(&self, tables: Vec<SQLTable>, source: &str) -> Vec<SQLTable> {
         let mut result = vec![];
         for table in tables {
-            if !self.is_table_tracked(&table) {
+            if !self.is_table_tracked(&table.table, source) {
                 result.push(table)
             }
         }
         result
     }
 
+    /// Tables untracked in `source` that are not in `ignore`, see `QualifiedTable::is_ignored`
+    pub fn select_untracked_tables(
+        &self,
+        tables: Vec<SQLTable>,
+        ignore: &[String],
+        source: &str,
+    ) -> Vec<SQLTable> {
+        self.get_untracked_tables(tables, source)
+            .into_iter()
+            .filter(|t| !t.table.is_ignored(ignore))
+            .collect()
+    }
+
+    /// Relationships of `relationships` not tracked in `source` yet, with their final
+    /// names and the foreign key each one is created from
     pub fn get_untracked_relationships<'a>(
         &'a self,
-        relationships: &'a Vec<SQLFKRelationship>,
+        relationships: &'a [SQLFKRelationship],
         source: &'a str,
-    ) -> Vec<CreateRelationship> {
-        let mut array_rels: Vec<CreateArrayRelationship> = vec![];
-        let mut object_rels: Vec<CreateObjectRelationship> = vec![];
+        driver: Driver,
+    ) -> Vec<(&'a SQLFKRelationship, CreateRelationship<'a>)> {
+        let mut array_rels: Vec<(&SQLFKRelationship, CreateRelationship)> = vec![];
+        let mut object_rels: Vec<(&SQLFKRelationship, CreateRelationship)> = vec![];
         for rel in relationships {
-            let (obj_rel, arr_rel) = rel.get_relationships(source);
-            if !self.is_relationship_tracked(&rel, RelType::Array, source) {
-                array_rels.push(arr_rel);
+            let (obj_rel, arr_rel) = rel.get_relationships(source, driver);
+            if !self.is_relationship_tracked(rel, RelType::Array, source) {
+                array_rels.push((rel, arr_rel.into()));
             }
-            if !self.is_relationship_tracked(&rel, RelType::Object, source) {
-                object_rels.push(obj_rel);
+            if !self.is_relationship_tracked(rel, RelType::Object, source) {
+                object_rels.push((rel, obj_rel.into()));
             }
         }
 
-        array_rels
+        let mut planned = array_rels
             .into_iter()
-            .map(|a| CreateRelationship::Array(a))
-            .chain(
-                object_rels
-                    .into_iter()
-                    .map(|o| CreateRelationship::Object(o)),
-            )
+            .chain(object_rels)
+            .collect::<Vec<_>>();
+        resolve_relationship_names(&mut planned, self.relationship_names(source));
+        planned
+    }
+
+    /// Names of the relationships already tracked on each table of `source`
+    pub fn relationship_names(&self, source: &str) -> HashMap<QualifiedTable, HashSet<String>> {
+        self.sources
+            .iter()
+            .filter(|s| s.name == source)
+            .flat_map(|s| s.tables())
+            .map(|te| {
+                let names = te
+                    .object_relationships
+                    .iter()
+                    .map(|rel| rel.name.clone())
+                    .chain(te.array_relationships.iter().map(|rel| rel.name.clone()))
+                    .collect();
+                (te.table.clone(), names)
+            })
+            .collect()
+    }
+
+    /// Functions returning a tracked table that are not tracked in `source` yet.
+    /// Overloaded functions are left out, see `types::overloaded_functions`.
+    pub fn get_untracked_functions(
+        &self,
+        functions: Vec<SQLFunction>,
+        source: &str,
+    ) -> Vec<QualifiedFunction> {
+        functions
+            .into_iter()
+            .filter(|f| !f.overloaded && self.is_table_tracked(&f.return_table, source))
+            .filter(|f| !self.is_function_tracked(&f.function, source))
+            .map(|f| f.function)
+            .collect()
+    }
+
+    /// Functions tracked in `source`
+    pub fn get_tracked_functions(&self, source: &str) -> Vec<&QualifiedFunction> {
+        self.sources
+            .iter()
+            .filter(|s| s.name == source)
+            .flat_map(|s| s.functions().map(|fe| &fe.function))
+            .collect()
+    }
+
+    pub fn is_function_tracked(&self, function: &QualifiedFunction, source: &str) -> bool {
+        self.sources
+            .iter()
+            .find(|&s| s.name == source)
+            .map(|s| s.functions().any(|fe| &fe.function == function))
+            .unwrap_or_default()
+    }
+
+    /// Tables tracked in `source`
+    pub fn get_tracked_tables(&self, source: &str) -> Vec<&QualifiedTable> {
+        self.sources
+            .iter()
+            .filter(|s| s.name == source)
+            .flat_map(|s| s.tables().map(|te| &te.table))
             .collect()
     }
 
-    pub fn is_table_tracked(&self, table: &QualifiedTable) -> bool {
+    pub fn is_table_tracked(&self, table: &QualifiedTable, source: &str) -> bool {
         self.sources
             .iter()
-            .any(|ms| ms.tables.iter().any(|te| &te.table == table))
+            .filter(|ms| ms.name == source)
+            .any(|ms| ms.tables().any(|te| &te.table == table))
     }
 
     pub fn is_relationship_tracked(
@@ -62,98 +182,1227 @@ impl Metadata {
         rel_type: RelType,
         source: &str,
     ) -> bool {
-        let arr_table = QualifiedTable {
-            name: relationship.ref_table_name.clone(),
-            schema: relationship.ref_table_schema.clone(),
-        };
-        let obj_table = QualifiedTable {
-            name: relationship.table_name.clone(),
-            schema: relationship.table_schema.clone(),
-        };
+        let obj_table = relationship.table();
         let rel_table = match rel_type {
-            RelType::Array => &arr_table,
-            RelType::Object => &obj_table,
+            RelType::Array => relationship.ref_table(),
+            RelType::Object => relationship.table(),
         };
+        let columns = relationship.column_mapping.keys().map(|c| &c[..]).collect();
         self.sources
             .iter()
             .find(|&s| s.name == source)
-            .map(|source| source.tables.iter().find(|&te| te.table == *rel_table))
-            .flatten()
-            .map(|te| {
-                &te.table == rel_table && {
-                    let rels = match &rel_type {
-                        RelType::Array => te
-                            .array_relationships
-                            .iter()
-                            .any(|rel| rel.using.foreign_key_constraint_on.table == obj_table),
-                        RelType::Object => te.object_relationships.iter().any(|rel| {
-                            relationship
-                                .column_mapping
-                                .contains_key(&rel.using.foreign_key_constraint_on)
-                        }),
-                    };
-                    rels
-                }
+            .and_then(|source| source.tables().find(|&te| te.table == rel_table))
+            .map(|te| match &rel_type {
+                RelType::Array => te.array_relationships.iter().any(|rel| {
+                    rel.using
+                        .foreign_key_constraint_on
+                        .as_ref()
+                        .is_some_and(|fkey| {
+                            fkey.table == obj_table && fkey.columns.columns() == columns
+                        })
+                }),
+                RelType::Object => te.object_relationships.iter().any(|rel| {
+                    rel.using
+                        .foreign_key_constraint_on
+                        .as_ref()
+                        .and_then(FKeyColumns::local_columns)
+                        .as_ref()
+                        == Some(&columns)
+                }),
             })
             .unwrap_or_default()
     }
+
+    /// Relationships of `source` using `foreign_key_constraint_on` for which no foreign
+    /// key in `relationships` exists anymore, as `(table, relationship name)`.
+    /// Relationships using `manual_configuration` are never dangling.
+    pub fn get_dangling_relationships(
+        &self,
+        relationships: &[SQLFKRelationship],
+        source: &str,
+    ) -> Vec<(&QualifiedTable, &str)> {
+        let backed = |table: &QualifiedTable,
+                      ref_table: Option<&QualifiedTable>,
+                      columns: BTreeSet<&str>| {
+            relationships.iter().any(|fk| {
+                &fk.table() == table
+                    && ref_table.is_none_or(|r| &fk.ref_table() == r)
+                    && fk
+                        .column_mapping
+                        .keys()
+                        .map(|c| &c[..])
+                        .collect::<BTreeSet<_>>()
+                        == columns
+            })
+        };
+        let mut dangling = vec![];
+        for te in self
+            .sources
+            .iter()
+            .filter(|s| s.name == source)
+            .flat_map(|s| s.tables())
+        {
+            for rel in &te.object_relationships {
+                let is_backed = match &rel.using.foreign_key_constraint_on {
+                    None => true,
+                    Some(FKeyColumns::Remote(fkey)) => {
+                        backed(&fkey.table, Some(&te.table), fkey.columns.columns())
+                    }
+                    Some(fkey) => backed(&te.table, None, fkey.columns()),
+                };
+                if !is_backed {
+                    dangling.push((&te.table, &rel.name[..]));
+                }
+            }
+            for rel in &te.array_relationships {
+                let is_backed = match &rel.using.foreign_key_constraint_on {
+                    None => true,
+                    Some(fkey) => backed(&fkey.table, Some(&te.table), fkey.columns.columns()),
+                };
+                if !is_backed {
+                    dangling.push((&te.table, &rel.name[..]));
+                }
+            }
+        }
+        dangling
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct MetadataSource {
-    name: String,
-    kind: Option<String>,
-    tables: Vec<TableEntry>,
-    #[serde(default)]
-    functions: Vec<FunctionEntry>,
+#[derive(Deserialize, Serialize, Debug)]
+pub struct MetadataSource {
+    pub(crate) name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) kind: Option<String>,
+    tables: Vec<SourceEntry<TableEntry>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    functions: Vec<SourceEntry<FunctionEntry>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) configuration: Option<SourceConfiguration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    customization: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    query_tags: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    health_check: Option<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+impl MetadataSource {
+    /// `kind` of the source, which Hasura omits for Postgres in older exports
+    pub fn kind(&self) -> &str {
+        self.kind.as_deref().unwrap_or("postgres")
+    }
+
+    /// Driver of the source's `kind`, or `fallback` for kinds this tool does not know
+    pub fn driver(&self, fallback: Driver) -> Driver {
+        Driver::from_kind(self.kind()).unwrap_or(fallback)
+    }
+
+    fn tables(&self) -> impl Iterator<Item = &TableEntry> {
+        self.tables.iter().filter_map(SourceEntry::modelled)
+    }
+
+    fn functions(&self) -> impl Iterator<Item = &FunctionEntry> {
+        self.functions.iter().filter_map(SourceEntry::modelled)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct SourceConfiguration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) connection_info: Option<ConnectionInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) read_replicas: Option<Vec<ConnectionInfo>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) extensions_schema: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Connection settings of a source. `database_url` is used by Postgres flavours,
+/// `connection_string` by MSSQL; both are a string or `{"from_env": "VAR"}`.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ConnectionInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) database_url: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) connection_string: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) isolation_level: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) use_prepared_statements: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pool_settings: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) ssl_configuration: Option<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Table or function of a source. Only Postgres-family and MSSQL identifiers are
+/// `{schema, name}`: BigQuery uses `{dataset, name}` and data connectors arrays such
+/// as `["Album"]`, so entries that do not fit the model are kept as they are.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+enum SourceEntry<T> {
+    Modelled(T),
+    Other(Value),
+}
+
+impl<T> SourceEntry<T> {
+    fn modelled(&self) -> Option<&T> {
+        match self {
+            SourceEntry::Modelled(entry) => Some(entry),
+            SourceEntry::Other(_) => None,
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 struct TableEntry {
     table: QualifiedTable,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    is_enum: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    configuration: Option<TableConfiguration>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     object_relationships: Vec<ObjectRelationships>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     array_relationships: Vec<ArrayRelationships>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    remote_relationships: Vec<RemoteRelationship>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    computed_fields: Vec<ComputedField>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    insert_permissions: Vec<PermissionEntry<InsertPermission>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    select_permissions: Vec<PermissionEntry<SelectPermission>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    update_permissions: Vec<PermissionEntry<UpdatePermission>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    delete_permissions: Vec<PermissionEntry<DeletePermission>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    event_triggers: Vec<EventTrigger>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TableConfiguration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    custom_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    custom_root_fields: Option<BTreeMap<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    custom_column_names: Option<BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    column_config: Option<BTreeMap<String, ColumnConfig>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, PartialOrd)]
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ColumnConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    custom_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct QualifiedTable {
     pub(crate) name: String,
     pub(crate) schema: String,
 }
 
+impl QualifiedTable {
+    /// Whether an `ignore` entry is this table's bare name (ignored in every schema)
+    /// or its `schema.name`
+    pub fn is_ignored(&self, ignore: &[String]) -> bool {
+        ignore.iter().any(|entry| match entry.split_once('.') {
+            Some((schema, name)) => schema == self.schema && name == self.name,
+            None => entry == &self.name,
+        })
+    }
+}
+
+impl Display for QualifiedTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.schema, self.name)
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ObjectRelationships {
     pub(crate) name: String,
     pub(crate) using: ObjRelUsing,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) comment: Option<String>,
+    #[serde(flatten)]
+    pub(crate) extra: Map<String, Value>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ObjRelUsing {
-    pub(crate) foreign_key_constraint_on: String, // no need to support manual configuration yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) foreign_key_constraint_on: Option<FKeyColumns>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) manual_configuration: Option<ManualConfiguration>,
+    #[serde(flatten)]
+    pub(crate) extra: Map<String, Value>,
 }
 
+/// Relationship defined by a column mapping instead of a foreign key
 #[derive(Deserialize, Serialize, Debug)]
-pub struct ArrayRelUsing {
-    pub(crate) foreign_key_constraint_on: ArrayRelUsingFKeyOn, // no need to support manual configuration yet
+pub struct ManualConfiguration {
+    pub(crate) remote_table: QualifiedTable,
+    pub(crate) column_mapping: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) insertion_order: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Columns of an object relationship's `foreign_key_constraint_on`: a single column
+/// name, a list of column names for composite foreign keys, or the columns of a
+/// foreign key on another table referencing this one
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum FKeyColumns {
+    Single(String),
+    Multiple(Vec<String>),
+    Remote(ArrayRelUsingFKeyOn),
+}
+
+impl FKeyColumns {
+    pub fn columns(&self) -> BTreeSet<&str> {
+        match self {
+            FKeyColumns::Single(column) => BTreeSet::from([&column[..]]),
+            FKeyColumns::Multiple(columns) => columns.iter().map(|c| &c[..]).collect(),
+            FKeyColumns::Remote(fkey) => fkey.columns.columns(),
+        }
+    }
+
+    /// Columns of a foreign key on the relationship's own table
+    pub fn local_columns(&self) -> Option<BTreeSet<&str>> {
+        match self {
+            FKeyColumns::Remote(_) => None,
+            _ => Some(self.columns()),
+        }
+    }
+}
+
+impl From<Vec<String>> for FKeyColumns {
+    fn from(mut columns: Vec<String>) -> Self {
+        match columns.len() {
+            1 => FKeyColumns::Single(columns.remove(0)),
+            _ => FKeyColumns::Multiple(columns),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
-pub(crate) struct ArrayRelUsingFKeyOn {
-    pub(crate) column: String,
+pub struct ArrayRelUsing {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) foreign_key_constraint_on: Option<ArrayRelUsingFKeyOn>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) manual_configuration: Option<ManualConfiguration>,
+    #[serde(flatten)]
+    pub(crate) extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ArrayRelUsingFKeyOn {
+    #[serde(flatten)]
+    pub(crate) columns: ArrayFKeyColumns,
     pub(crate) table: QualifiedTable,
 }
 
+/// Columns of an array relationship's `foreign_key_constraint_on`, serialized
+/// as `column` for a single column and `columns` for composite foreign keys
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub enum ArrayFKeyColumns {
+    #[serde(rename = "column")]
+    Single(String),
+    #[serde(rename = "columns")]
+    Multiple(Vec<String>),
+}
+
+impl ArrayFKeyColumns {
+    pub fn columns(&self) -> BTreeSet<&str> {
+        match self {
+            ArrayFKeyColumns::Single(column) => BTreeSet::from([&column[..]]),
+            ArrayFKeyColumns::Multiple(columns) => columns.iter().map(|c| &c[..]).collect(),
+        }
+    }
+}
+
+impl From<Vec<String>> for ArrayFKeyColumns {
+    fn from(mut columns: Vec<String>) -> Self {
+        match columns.len() {
+            1 => ArrayFKeyColumns::Single(columns.remove(0)),
+            _ => ArrayFKeyColumns::Multiple(columns),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ArrayRelationships {
     pub(crate) name: String,
     pub(crate) using: ArrayRelUsing,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) comment: Option<String>,
+    #[serde(flatten)]
+    pub(crate) extra: Map<String, Value>,
+}
+
+/// Relationship to a remote schema or another source. The shape of `definition`
+/// depends on the target, so it is kept as is.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RemoteRelationship {
+    name: String,
+    definition: Value,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ComputedField {
+    name: String,
+    definition: ComputedFieldDefinition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ComputedFieldDefinition {
+    function: QualifiedFunction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    table_argument: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    session_argument: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Permission of `role`. Boolean expressions (`filter`, `check`) and column presets
+/// (`set`) are arbitrary JSON and kept as is.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PermissionEntry<P> {
+    role: String,
+    permission: P,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// `"*"` or a list of column names
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+pub enum PermissionColumns {
+    All(String),
+    Columns(Vec<String>),
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct InsertPermission {
+    check: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    set: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    columns: Option<PermissionColumns>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    backend_only: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    validate_input: Option<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SelectPermission {
+    columns: PermissionColumns,
+    filter: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    computed_fields: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    limit: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    allow_aggregations: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    query_root_fields: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    subscription_root_fields: Option<Vec<String>>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UpdatePermission {
+    columns: PermissionColumns,
+    filter: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    check: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    set: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    backend_only: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    validate_input: Option<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DeletePermission {
+    filter: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    backend_only: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    validate_input: Option<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct EventTrigger {
+    name: String,
+    definition: EventTriggerDefinition,
+    retry_conf: RetryConf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    webhook: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    webhook_from_env: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<Header>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_transform: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response_transform: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cleanup_config: Option<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Operations firing an event trigger, with the columns (`"*"` or a list) of each
+#[derive(Deserialize, Serialize, Debug)]
+pub struct EventTriggerDefinition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    enable_manual: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    insert: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    update: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    delete: Option<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RetryConf {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    num_retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    interval_sec: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeout_sec: Option<u32>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Header sent to a webhook or remote schema, with a literal value or one read from an env var
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Header {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value_from_env: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
 }
 
-type QualifiedFunction = QualifiedTable;
+pub type QualifiedFunction = QualifiedTable;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 struct FunctionEntry {
     function: QualifiedFunction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    configuration: Option<FunctionConfiguration>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    permissions: Vec<RolePermission>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct FunctionConfiguration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    custom_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    custom_root_fields: Option<BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    session_argument: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exposed_as: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Permission of a function or action, which only grants access to `role`
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RolePermission {
+    role: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RemoteSchema {
+    name: String,
+    definition: RemoteSchemaDefinition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    permissions: Vec<RemoteSchemaPermission>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    remote_relationships: Vec<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RemoteSchemaDefinition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url_from_env: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeout_seconds: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    forward_client_headers: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<Header>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    customization: Option<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Role based schema of a remote schema, as GraphQL SDL
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RemoteSchemaPermission {
+    role: String,
+    definition: RemoteSchemaPermissionDefinition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RemoteSchemaPermissionDefinition {
+    schema: String,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct QueryCollection {
+    name: String,
+    definition: QueryCollectionDefinition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct QueryCollectionDefinition {
+    queries: Vec<NamedQuery>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct NamedQuery {
+    name: String,
+    query: String,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AllowlistEntry {
+    collection: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scope: Option<AllowlistScope>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// `{"global": true}`, or `{"global": false, "roles": [..]}` for role based allowlists
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AllowlistScope {
+    global: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    roles: Option<Vec<String>>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Action {
+    name: String,
+    definition: ActionDefinition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    permissions: Vec<RolePermission>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ActionDefinition {
+    handler: String,
+    output_type: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    arguments: Vec<ActionArgument>,
+    /// `query` or `mutation`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    r#type: Option<String>,
+    /// `synchronous` or `asynchronous`, for mutations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<Header>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    forward_client_headers: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeout: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_transform: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response_transform: Option<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ActionArgument {
+    name: String,
+    r#type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// GraphQL types used by actions
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CustomTypes {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    input_objects: Vec<CustomObjectType>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    objects: Vec<CustomObjectType>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    scalars: Vec<CustomScalarType>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    enums: Vec<CustomEnumType>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CustomObjectType {
+    name: String,
+    fields: Vec<ActionArgument>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    /// Relationships of output objects to tables
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    relationships: Option<Vec<Value>>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CustomScalarType {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CustomEnumType {
+    name: String,
+    values: Vec<CustomEnumValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CustomEnumValue {
+    value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    is_deprecated: Option<bool>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CronTrigger {
+    name: String,
+    webhook: String,
+    schedule: String,
+    include_in_metadata: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_conf: Option<CronRetryConf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<Header>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_transform: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response_transform: Option<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CronRetryConf {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    num_retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_interval_seconds: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeout_seconds: Option<u32>,
+    /// How late a scheduled event may still be delivered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tolerance_seconds: Option<u32>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RestEndpoint {
+    name: String,
+    url: String,
+    methods: Vec<String>,
+    definition: RestEndpointDefinition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Query of a REST endpoint, taken from a query collection
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RestEndpointDefinition {
+    query: RestEndpointQuery,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RestEndpointQuery {
+    query_name: String,
+    collection_name: String,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Role inheriting the permissions of `role_set`
+#[derive(Deserialize, Serialize, Debug)]
+pub struct InheritedRole {
+    role_name: String,
+    role_set: Vec<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::types::TableKind;
+
+    fn metadata(tables: &[(&str, &str)]) -> Metadata {
+        let tables = tables
+            .iter()
+            .map(|(schema, name)| json!({ "table": { "schema": schema, "name": name } }))
+            .collect::<Vec<_>>();
+        serde_json::from_value(json!({
+            "version": 3,
+            "sources": [{ "name": "default", "kind": "postgres", "tables": tables }]
+        }))
+        .unwrap()
+    }
+
+    fn table(schema: &str, name: &str) -> QualifiedTable {
+        QualifiedTable {
+            name: name.to_string(),
+            schema: schema.to_string(),
+        }
+    }
+
+    fn sql_tables(tables: &[(&str, &str)]) -> Vec<SQLTable> {
+        tables
+            .iter()
+            .map(|(schema, name)| SQLTable {
+                table: table(schema, name),
+                kind: TableKind::Table,
+            })
+            .collect()
+    }
+
+    fn names(tables: &[SQLTable]) -> Vec<String> {
+        tables.iter().map(|t| t.table.to_string()).collect()
+    }
+
+    #[test]
+    fn get_untracked_tables_skips_tracked_tables() {
+        let metadata = metadata(&[("public", "users")]);
+        let tables = sql_tables(&[("public", "users"), ("public", "orders")]);
+        let untracked = metadata.get_untracked_tables(tables, "default");
+        assert_eq!(names(&untracked), ["public.orders"]);
+    }
+
+    #[test]
+    fn get_untracked_tables_compares_schema() {
+        let metadata = metadata(&[("public", "users")]);
+        let tables = sql_tables(&[("public", "users"), ("archive", "users")]);
+        let untracked = metadata.get_untracked_tables(tables, "default");
+        assert_eq!(names(&untracked), ["archive.users"]);
+    }
+
+    #[test]
+    fn get_untracked_tables_with_empty_metadata() {
+        let metadata = metadata(&[]);
+        let tables = sql_tables(&[("public", "users"), ("public", "orders")]);
+        let untracked = metadata.get_untracked_tables(tables, "default");
+        assert_eq!(names(&untracked), ["public.users", "public.orders"]);
+    }
+
+    #[test]
+    fn get_untracked_tables_when_everything_is_tracked() {
+        let metadata = metadata(&[("public", "users")]);
+        let untracked =
+            metadata.get_untracked_tables(sql_tables(&[("public", "users")]), "default");
+        assert!(untracked.is_empty());
+    }
+
+    #[test]
+    fn get_untracked_tables_is_scoped_to_the_source() {
+        let metadata = metadata(&[("public", "users")]);
+        let tables = sql_tables(&[("public", "users")]);
+        let untracked = metadata.get_untracked_tables(tables, "analytics");
+        assert_eq!(names(&untracked), ["public.users"]);
+        assert!(!metadata.is_table_tracked(&table("public", "users"), "analytics"));
+        assert!(metadata.is_table_tracked(&table("public", "users"), "default"));
+    }
+
+    #[test]
+    fn select_untracked_tables_without_ignore() {
+        let metadata = metadata(&[("public", "users")]);
+        let tables = sql_tables(&[("public", "users"), ("public", "orders")]);
+        let selected = metadata.select_untracked_tables(tables, &[], "default");
+        assert_eq!(names(&selected), ["public.orders"]);
+    }
+
+    #[test]
+    fn select_untracked_tables_ignores_by_name_in_every_schema() {
+        let metadata = metadata(&[]);
+        let tables = sql_tables(&[
+            ("public", "audit"),
+            ("archive", "audit"),
+            ("public", "orders"),
+        ]);
+        let selected = metadata.select_untracked_tables(tables, &["audit".to_string()], "default");
+        assert_eq!(names(&selected), ["public.orders"]);
+    }
+
+    #[test]
+    fn select_untracked_tables_ignores_by_qualified_name() {
+        let metadata = metadata(&[]);
+        let tables = sql_tables(&[("public", "audit"), ("archive", "audit")]);
+        let selected =
+            metadata.select_untracked_tables(tables, &["archive.audit".to_string()], "default");
+        assert_eq!(names(&selected), ["public.audit"]);
+    }
+
+    #[test]
+    fn is_ignored() {
+        let users = table("public", "users");
+        assert!(!users.is_ignored(&[]));
+        assert!(users.is_ignored(&["users".to_string()]));
+        assert!(users.is_ignored(&["public.users".to_string()]));
+        assert!(!users.is_ignored(&["archive.users".to_string()]));
+        assert!(!users.is_ignored(&["user".to_string()]));
+    }
+
+    fn relationships_metadata() -> Metadata {
+        serde_json::from_value(json!({
+            "version": 3,
+            "sources": [{
+                "name": "default",
+                "kind": "postgres",
+                "tables": [
+                    {
+                        "table": { "schema": "public", "name": "orders" },
+                        "object_relationships": [
+                            { "name": "user", "using": { "foreign_key_constraint_on": "user_id" } },
+                            { "name": "coupon", "using": { "foreign_key_constraint_on": "coupon_id" } },
+                            {
+                                "name": "invoice",
+                                "using": { "manual_configuration": {
+                                    "remote_table": { "schema": "public", "name": "invoices" },
+                                    "column_mapping": { "id": "order_id" }
+                                } }
+                            }
+                        ]
+                    },
+                    {
+                        "table": { "schema": "public", "name": "users" },
+                        "array_relationships": [
+                            {
+                                "name": "orders",
+                                "using": { "foreign_key_constraint_on": {
+                                    "column": "user_id",
+                                    "table": { "schema": "public", "name": "orders" }
+                                } }
+                            },
+                            {
+                                "name": "reviews",
+                                "using": { "foreign_key_constraint_on": {
+                                    "column": "user_id",
+                                    "table": { "schema": "public", "name": "reviews" }
+                                } }
+                            }
+                        ]
+                    }
+                ]
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn relationship_is_tracked_only_with_the_whole_column_set() {
+        let fk: SQLFKRelationship = serde_json::from_value(json!({
+            "table_schema": "public",
+            "table_name": "orders",
+            "constraint_name": "orders_tenant_id_user_id_fkey",
+            "ref_table_schema": "public",
+            "ref_table_name": "users",
+            "column_mapping": { "tenant_id": "tenant_id", "user_id": "id" }
+        }))
+        .unwrap();
+        let tracked_with = |object_columns: Value, array_columns: Value| -> Metadata {
+            serde_json::from_value(json!({
+                "version": 3,
+                "sources": [{
+                    "name": "default",
+                    "tables": [
+                        {
+                            "table": { "schema": "public", "name": "orders" },
+                            "object_relationships": [
+                                { "name": "user", "using": { "foreign_key_constraint_on": object_columns } }
+                            ]
+                        },
+                        {
+                            "table": { "schema": "public", "name": "users" },
+                            "array_relationships": [{
+                                "name": "orders",
+                                "using": { "foreign_key_constraint_on": {
+                                    "columns": array_columns,
+                                    "table": { "schema": "public", "name": "orders" }
+                                } }
+                            }]
+                        }
+                    ]
+                }]
+            }))
+            .unwrap()
+        };
+        let partial = tracked_with(json!("tenant_id"), json!(["user_id"]));
+        assert!(!partial.is_relationship_tracked(&fk, RelType::Object, "default"));
+        assert!(!partial.is_relationship_tracked(&fk, RelType::Array, "default"));
+        let whole = tracked_with(
+            json!(["user_id", "tenant_id"]),
+            json!(["tenant_id", "user_id"]),
+        );
+        assert!(whole.is_relationship_tracked(&fk, RelType::Object, "default"));
+        assert!(whole.is_relationship_tracked(&fk, RelType::Array, "default"));
+    }
+
+    #[test]
+    fn get_dangling_relationships_finds_relationships_without_foreign_key() {
+        let metadata = relationships_metadata();
+        let fks: Vec<SQLFKRelationship> = serde_json::from_value(json!([{
+            "table_schema": "public",
+            "table_name": "orders",
+            "constraint_name": "orders_user_id_fkey",
+            "ref_table_schema": "public",
+            "ref_table_name": "users",
+            "column_mapping": { "user_id": "id" }
+        }]))
+        .unwrap();
+        let dangling = metadata.get_dangling_relationships(&fks, "default");
+        assert_eq!(
+            dangling,
+            [
+                (&table("public", "orders"), "coupon"),
+                (&table("public", "users"), "reviews")
+            ]
+        );
+        assert!(metadata
+            .get_dangling_relationships(&fks, "other")
+            .is_empty());
+    }
+
+    #[test]
+    fn get_untracked_relationships_uses_the_driver_prefix() {
+        let metadata: Metadata = serde_json::from_value(json!({
+            "version": 3,
+            "sources": [{ "name": "default", "kind": "mssql", "tables": [] }]
+        }))
+        .unwrap();
+        let fks: Vec<SQLFKRelationship> = serde_json::from_value(json!([{
+            "table_schema": "dbo",
+            "table_name": "orders",
+            "constraint_name": "FK_orders_users",
+            "ref_table_schema": "dbo",
+            "ref_table_name": "users",
+            "column_mapping": { "user_id": "id" }
+        }]))
+        .unwrap();
+        let types = metadata
+            .get_untracked_relationships(&fks, "default", Driver::Mssql)
+            .iter()
+            .map(|(_, rel)| serde_json::to_value(rel).unwrap()["type"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                "mssql_create_array_relationship",
+                "mssql_create_object_relationship"
+            ]
+        );
+    }
+
+    #[test]
+    fn untracked_relationships_are_summarized_with_their_final_names() {
+        let metadata: Metadata = serde_json::from_value(json!({
+            "version": 3,
+            "sources": [{ "name": "default", "tables": [] }]
+        }))
+        .unwrap();
+        let fk = |column: &str, constraint: &str| {
+            json!({
+                "table_schema": "public",
+                "table_name": "orders",
+                "constraint_name": constraint,
+                "ref_table_schema": "public",
+                "ref_table_name": "users",
+                "column_mapping": { column: "id" }
+            })
+        };
+        let fks: Vec<SQLFKRelationship> = serde_json::from_value(json!([
+            fk("seller_id", "orders_seller_id_fkey"),
+            fk("buyer_id", "orders_buyer_id_fkey"),
+        ]))
+        .unwrap();
+        let mut summary = metadata
+            .get_untracked_relationships(&fks, "default", Driver::Pg)
+            .iter()
+            .map(|(fk, rel)| rel.summary(fk))
+            .collect::<Vec<_>>();
+        summary.sort();
+        assert_eq!(
+            summary,
+            [
+                "create array relationship buyer_orders on public.users  -  public.orders.buyer_id --> public.users.id",
+                "create array relationship seller_orders on public.users  -  public.orders.seller_id --> public.users.id",
+                "create object relationship buyer on public.orders  -  public.orders.buyer_id --> public.users.id",
+                "create object relationship seller on public.orders  -  public.orders.seller_id --> public.users.id",
+            ]
+        );
+    }
+
+    fn assert_round_trip(fixture: &str) {
+        let document: Value = serde_json::from_str(fixture).unwrap();
+        let metadata: Metadata = serde_json::from_value(document.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&metadata).unwrap(), document);
+    }
+
+    #[test]
+    fn round_trips_minimal_metadata() {
+        assert_round_trip(include_str!("../tests/fixtures/metadata/minimal.json"));
+    }
+
+    #[test]
+    fn round_trips_full_metadata() {
+        assert_round_trip(include_str!("../tests/fixtures/metadata/full.json"));
+    }
+
+    #[test]
+    fn round_trips_mssql_metadata() {
+        assert_round_trip(include_str!("../tests/fixtures/metadata/mssql.json"));
+    }
+
+    #[test]
+    fn round_trips_unmodelled_fields() {
+        let fixture = include_str!("../tests/fixtures/metadata/unmodelled.json");
+        assert_round_trip(fixture);
+        let metadata: Metadata = serde_json::from_str(fixture).unwrap();
+        let source = &metadata.sources[0];
+        assert!(source.extra.contains_key("native_queries"));
+        let table = source.tables().next().unwrap();
+        assert!(table.extra.contains_key("apollo_federation_config"));
+        let configuration = source.configuration.as_ref().unwrap();
+        assert!(configuration.extra.contains_key("connection_template"));
+    }
+
+    #[test]
+    fn round_trips_sources_with_other_identifiers() {
+        let fixture = include_str!("../tests/fixtures/metadata/other_backends.json");
+        assert_round_trip(fixture);
+        let metadata: Metadata = serde_json::from_str(fixture).unwrap();
+        assert_eq!(
+            metadata.get_tracked_tables("default"),
+            [&QualifiedTable {
+                schema: "public".to_string(),
+                name: "users".to_string()
+            }]
+        );
+        assert!(metadata.get_tracked_tables("analytics").is_empty());
+        assert!(metadata.get_tracked_functions("analytics").is_empty());
+        assert!(metadata.get_tracked_tables("chinook").is_empty());
+    }
 }
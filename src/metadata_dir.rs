@@ -0,0 +1,639 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::ArgEnum;
+use serde_json::{json, Map, Value};
+
+use crate::error::{HasuraUtilsError, OtherError};
+
+/// Format of the files of a `MetadataDir`
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum FileFormat {
+    Yaml,
+    Json,
+}
+
+impl FileFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            FileFormat::Yaml => "yaml",
+            FileFormat::Json => "json",
+        }
+    }
+
+    fn serialize(self, value: &Value) -> Result<String, HasuraUtilsError> {
+        Ok(match self {
+            FileFormat::Yaml => serde_yaml::to_string(value)?,
+            FileFormat::Json => serde_json::to_string_pretty(value)? + "\n",
+        })
+    }
+
+    fn deserialize(self, content: &str) -> Result<Value, HasuraUtilsError> {
+        Ok(match self {
+            FileFormat::Yaml => serde_yaml::from_str(content)?,
+            FileFormat::Json => serde_json::from_str(content)?,
+        })
+    }
+}
+
+/// Top level keys of the metadata, whose files in the root directory belong to the export
+const METADATA_KEYS: [&str; 15] = [
+    "version",
+    "remote_schemas",
+    "query_collections",
+    "allowlist",
+    "actions",
+    "custom_types",
+    "cron_triggers",
+    "rest_endpoints",
+    "inherited_roles",
+    "api_limits",
+    "graphql_schema_introspection",
+    "network",
+    "backend_configs",
+    "metrics_config",
+    "opentelemetry",
+];
+
+/// Lists the top level keys of the last export, so that keys this version does not
+/// know about are read back and cleaned up like the others
+const MANIFEST: &str = ".metadata_keys";
+
+/// Metadata exported to one file per source, table and function, so that
+/// changes can be reviewed in pull requests
+///
+/// ```text
+/// version.yaml
+/// actions.yaml, remote_schemas.yaml, ...   every other top level key
+/// sources/<source>/source.yaml             the source without tables and functions
+/// sources/<source>/tables/<schema>.<name>.yaml
+/// sources/<source>/functions/<schema>.<name>.yaml
+/// ```
+///
+/// Other files, e.g. a `docker-compose.yaml` next to the metadata, are left alone.
+pub struct MetadataDir {
+    root: PathBuf,
+    format: FileFormat,
+}
+
+impl MetadataDir {
+    pub fn new(root: impl Into<PathBuf>, format: FileFormat) -> Self {
+        Self {
+            root: root.into(),
+            format,
+        }
+    }
+
+    /// Writes the `export_metadata` document `metadata` and removes the files of
+    /// objects that no longer exist. Returns the written files.
+    ///
+    /// Every file is serialized and named before the first one is written, so that a
+    /// failure leaves the directory as it was. Objects whose file names only differ by
+    /// case, e.g. tables `users` and `"Users"`, get a `~2`, `~3`, ... suffix so that they
+    /// do not overwrite each other on case insensitive file systems.
+    pub fn write(&self, metadata: &Value) -> Result<Vec<PathBuf>, HasuraUtilsError> {
+        let metadata = metadata
+            .as_object()
+            .ok_or(OtherError("Exported metadata is not an object"))?;
+        let previous_keys = self.root_keys()?;
+        let mut files = Files::default();
+        for (key, value) in metadata {
+            let content = match key.as_str() {
+                "sources" => continue,
+                "version" => json!({ "version": value }),
+                _ => value.clone(),
+            };
+            self.plan_file(&mut files, &self.root, &escape(key), &content)?;
+        }
+        let sources = metadata.get("sources").and_then(Value::as_array);
+        let mut source_dirs = HashSet::new();
+        for source in sources.into_iter().flatten() {
+            let mut source = source.as_object().cloned().unwrap_or_default();
+            let name = source
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            let dir_name = unique_name(&mut source_dirs, escape(name));
+            let dir = self.root.join("sources").join(dir_name);
+            for (key, object_key) in [("tables", "table"), ("functions", "function")] {
+                let entries = source.remove(key).unwrap_or_default();
+                for entry in entries.as_array().into_iter().flatten() {
+                    let stem = file_stem(&entry[object_key]);
+                    self.plan_file(&mut files, &dir.join(key), &stem, entry)?;
+                }
+            }
+            self.plan_file(&mut files, &dir, "source", &Value::Object(source))?;
+        }
+        for (path, content) in &files.contents {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|err| HasuraUtilsError::Io(dir.into(), err))?;
+            }
+            fs::write(path, content).map_err(|err| HasuraUtilsError::Io(path.clone(), err))?;
+        }
+        let files = files
+            .contents
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>();
+
+        let written = files.iter().collect::<HashSet<_>>();
+        for key in previous_keys {
+            let path = self.path(&self.root, &key);
+            if path.is_file() && !written.contains(&path) {
+                fs::remove_file(&path).map_err(|err| HasuraUtilsError::Io(path, err))?;
+            }
+        }
+        for dir in subdirs(&self.root.join("sources"))? {
+            for key in ["tables", "functions"] {
+                self.remove_stale(&dir.join(key), &written)?;
+            }
+            let source_file = self.path(&dir, "source");
+            if source_file.is_file() && !written.contains(&source_file) {
+                fs::remove_file(&source_file)
+                    .map_err(|err| HasuraUtilsError::Io(source_file, err))?;
+            }
+            remove_if_empty(&dir)?;
+        }
+        self.write_manifest(metadata.keys().filter(|k| *k != "sources"))?;
+        Ok(files)
+    }
+
+    /// Reassembles the `export_metadata` document from the files written by `write`
+    pub fn read(&self) -> Result<Value, HasuraUtilsError> {
+        let mut metadata = Map::new();
+        for key in self.root_keys()? {
+            let path = self.path(&self.root, &key);
+            if !path.is_file() {
+                continue;
+            }
+            let value = self.read_file(&path)?;
+            match key.as_str() {
+                "version" => metadata.insert(key, value["version"].clone()),
+                _ => metadata.insert(key, value),
+            };
+        }
+        if !metadata.contains_key("version") {
+            return Err(OtherError("Metadata directory has no version file").into());
+        }
+        let mut sources = vec![];
+        for dir in subdirs(&self.root.join("sources"))? {
+            let source_file = self.path(&dir, "source");
+            if !source_file.is_file() {
+                continue;
+            }
+            let mut source = match self.read_file(&source_file)? {
+                Value::Object(source) => source,
+                _ => return Err(OtherError("Source file is not an object").into()),
+            };
+            for key in ["tables", "functions"] {
+                let entries = self.read_files(&dir.join(key))?;
+                if key == "tables" || !entries.is_empty() {
+                    source.insert(key.to_string(), Value::Array(entries));
+                }
+            }
+            sources.push(Value::Object(source));
+        }
+        metadata.insert("sources".to_string(), Value::Array(sources));
+        Ok(Value::Object(metadata))
+    }
+
+    /// Known top level keys and those listed in the manifest of the last export
+    fn root_keys(&self) -> Result<BTreeSet<String>, HasuraUtilsError> {
+        let mut keys = METADATA_KEYS
+            .iter()
+            .map(|k| k.to_string())
+            .collect::<BTreeSet<_>>();
+        let manifest = self.root.join(MANIFEST);
+        if manifest.is_file() {
+            let content = fs::read_to_string(&manifest)
+                .map_err(|err| HasuraUtilsError::Io(manifest.clone(), err))?;
+            keys.extend(content.lines().filter(|l| !l.is_empty()).map(unescape));
+        }
+        Ok(keys)
+    }
+
+    fn write_manifest<'a>(
+        &self,
+        keys: impl Iterator<Item = &'a String>,
+    ) -> Result<(), HasuraUtilsError> {
+        let path = self.root.join(MANIFEST);
+        let content = keys.map(|k| escape(k) + "\n").collect::<String>();
+        fs::write(&path, content).map_err(|err| HasuraUtilsError::Io(path, err))
+    }
+
+    fn path(&self, dir: &Path, stem: &str) -> PathBuf {
+        dir.join(format!("{stem}.{}", self.format.extension()))
+    }
+
+    fn read_file(&self, path: &Path) -> Result<Value, HasuraUtilsError> {
+        let content =
+            fs::read_to_string(path).map_err(|err| HasuraUtilsError::Io(path.into(), err))?;
+        self.format.deserialize(&content)
+    }
+
+    /// Contents of the files of this format in `dir` sorted by name. A missing
+    /// directory has no files.
+    fn read_files(&self, dir: &Path) -> Result<Vec<Value>, HasuraUtilsError> {
+        self.files(dir)?
+            .iter()
+            .map(|path| self.read_file(path))
+            .collect()
+    }
+
+    /// Files of this format in `dir` sorted by name. A missing directory has none.
+    fn files(&self, dir: &Path) -> Result<Vec<PathBuf>, HasuraUtilsError> {
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let io_err = |err| HasuraUtilsError::Io(dir.into(), err);
+        let mut paths = vec![];
+        for entry in fs::read_dir(dir).map_err(io_err)? {
+            let path = entry.map_err(io_err)?.path();
+            if path.is_file()
+                && path.extension().and_then(|e| e.to_str()) == Some(self.format.extension())
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Serializes `value` for `<dir>/<stem>.<ext>`, suffixing the stem when another
+    /// file of this export already has that path regardless of case
+    fn plan_file(
+        &self,
+        files: &mut Files,
+        dir: &Path,
+        stem: &str,
+        value: &Value,
+    ) -> Result<(), HasuraUtilsError> {
+        let dir_name = dir.to_string_lossy().to_lowercase();
+        let names = files.names.entry(dir_name).or_default();
+        let stem = unique_name(names, stem.to_string());
+        let content = self.format.serialize(value)?;
+        files.contents.push((self.path(dir, &stem), content));
+        Ok(())
+    }
+
+    /// Removes the files of this format in `dir` that were not just written, and
+    /// `dir` itself when left empty
+    fn remove_stale(
+        &self,
+        dir: &Path,
+        written: &HashSet<&PathBuf>,
+    ) -> Result<(), HasuraUtilsError> {
+        for path in self.files(dir)? {
+            if !written.contains(&path) {
+                fs::remove_file(&path).map_err(|err| HasuraUtilsError::Io(path, err))?;
+            }
+        }
+        remove_if_empty(dir)
+    }
+}
+
+/// Files of an export, named and serialized before any is written
+#[derive(Default)]
+struct Files {
+    contents: Vec<(PathBuf, String)>,
+    /// Lowercased stems taken in each lowercased directory
+    names: HashMap<String, HashSet<String>>,
+}
+
+/// `name`, or `name~2`, `name~3`, ... when `taken` already has it regardless of case.
+/// The suffix never needs to be parsed back, since `read` takes the names of the
+/// objects from the contents of the files.
+fn unique_name(taken: &mut HashSet<String>, name: String) -> String {
+    let name = match taken.contains(&name.to_lowercase()) {
+        false => name,
+        true => (2..)
+            .map(|n| format!("{name}~{n}"))
+            .find(|candidate| !taken.contains(&candidate.to_lowercase()))
+            .unwrap(),
+    };
+    taken.insert(name.to_lowercase());
+    name
+}
+
+fn remove_if_empty(dir: &Path) -> Result<(), HasuraUtilsError> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    let io_err = |err| HasuraUtilsError::Io(dir.into(), err);
+    if fs::read_dir(dir).map_err(io_err)?.next().is_none() {
+        fs::remove_dir(dir).map_err(io_err)?;
+    }
+    Ok(())
+}
+
+/// Subdirectories of `dir` sorted by name. A missing directory has none.
+fn subdirs(dir: &Path) -> Result<Vec<PathBuf>, HasuraUtilsError> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let io_err = |err| HasuraUtilsError::Io(dir.into(), err);
+    let mut dirs = vec![];
+    for entry in fs::read_dir(dir).map_err(io_err)? {
+        let path = entry.map_err(io_err)?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// `<schema>.<name>` of a table or function with both parts escaped, so that
+/// `a.b` + `c` and `a` + `b.c` get different files
+fn file_stem(object: &Value) -> String {
+    match name_parts(object) {
+        Some(parts) => parts.into_iter().map(escape).collect::<Vec<_>>().join("."),
+        None => escape(&object.to_string()),
+    }
+}
+
+/// Parts of a table or function identifier: `{schema, name}` on Postgres and MSSQL,
+/// `{dataset, name}` on BigQuery, `{name}` for remote schemas and the like, or an
+/// array of strings on data connectors
+fn name_parts(object: &Value) -> Option<Vec<&str>> {
+    if let Some(parts) = object.as_array() {
+        return parts.iter().map(Value::as_str).collect();
+    }
+    let namespace = object["schema"]
+        .as_str()
+        .or_else(|| object["dataset"].as_str());
+    let name = object["name"].as_str()?;
+    Some(namespace.into_iter().chain([name]).collect())
+}
+
+/// Percent-encodes the characters that are not safe in a file name: path
+/// separators, `.` (so that no name is `..` or hidden) and `%` itself
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '%' | '/' | '\\' | '.' | ':' | '\0' => escaped.push_str(&format!("%{:02X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(name: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = name.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|h| std::str::from_utf8(h).ok());
+        match (b, hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// `<schema>.<name>` of a table or function
+pub fn qualified_name(object: &Value) -> String {
+    match name_parts(object) {
+        Some(parts) => parts.join("."),
+        None => object.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hasura_utils_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn metadata(tables: &[&str]) -> Value {
+        let tables = tables
+            .iter()
+            .map(|name| json!({ "table": { "schema": "public", "name": name } }))
+            .collect::<Vec<_>>();
+        json!({
+            "version": 3,
+            "sources": [{
+                "name": "default",
+                "kind": "postgres",
+                "tables": tables,
+                "functions": [{ "function": { "schema": "public", "name": "search" } }],
+                "configuration": { "connection_info": {} }
+            }],
+            "actions": [{ "name": "login" }]
+        })
+    }
+
+    #[test]
+    fn write_splits_metadata_into_files() {
+        let root = temp_dir("write");
+        let files = MetadataDir::new(&root, FileFormat::Yaml)
+            .write(&metadata(&["users"]))
+            .unwrap();
+        let mut relative = files
+            .iter()
+            .map(|f| f.strip_prefix(&root).unwrap().to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        relative.sort();
+        assert_eq!(
+            relative,
+            [
+                "actions.yaml",
+                "sources/default/functions/public.search.yaml",
+                "sources/default/source.yaml",
+                "sources/default/tables/public.users.yaml",
+                "version.yaml",
+            ]
+        );
+        let source: Value = serde_yaml::from_str(
+            &fs::read_to_string(root.join("sources/default/source.yaml")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(source["kind"], "postgres");
+        assert!(source.get("tables").is_none());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn read_reassembles_written_metadata() {
+        let root = temp_dir("read");
+        let dir = MetadataDir::new(&root, FileFormat::Yaml);
+        let metadata = metadata(&["users", "orders"]);
+        dir.write(&metadata).unwrap();
+        let read = dir.read().unwrap();
+        assert_eq!(read["version"], 3);
+        assert_eq!(read["actions"], metadata["actions"]);
+        let source = &read["sources"][0];
+        assert_eq!(
+            source["configuration"],
+            metadata["sources"][0]["configuration"]
+        );
+        assert_eq!(source["functions"], metadata["sources"][0]["functions"]);
+        assert_eq!(source["tables"][0]["table"]["name"], "orders");
+        assert_eq!(source["tables"][1]["table"]["name"], "users");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn write_removes_files_of_dropped_objects() {
+        let root = temp_dir("stale");
+        let dir = MetadataDir::new(&root, FileFormat::Json);
+        dir.write(&metadata(&["users", "orders"])).unwrap();
+        fs::write(root.join("README.md"), "metadata").unwrap();
+        dir.write(&metadata(&["users"])).unwrap();
+        let tables = root.join("sources/default/tables");
+        assert!(tables.join("public.users.json").exists());
+        assert!(!tables.join("public.orders.json").exists());
+        assert!(root.join("README.md").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn write_keeps_foreign_files_and_removes_dropped_keys() {
+        let root = temp_dir("foreign");
+        let dir = MetadataDir::new(&root, FileFormat::Yaml);
+        let mut metadata = metadata(&["users"]);
+        metadata["native_queries"] = json!([{ "root_field_name": "q" }]);
+        dir.write(&metadata).unwrap();
+        fs::write(root.join("docker-compose.yaml"), "services: {}").unwrap();
+        fs::write(root.join("sources/default/notes.yaml"), "todo: []").unwrap();
+        let metadata = metadata_without(&metadata, &["actions", "native_queries"]);
+        dir.write(&metadata).unwrap();
+        assert!(root.join("docker-compose.yaml").exists());
+        assert!(root.join("sources/default/notes.yaml").exists());
+        assert!(!root.join("actions.yaml").exists());
+        assert!(!root.join("native_queries.yaml").exists());
+        let read = dir.read().unwrap();
+        assert!(read.get("docker-compose").is_none());
+        assert!(read.get("actions").is_none());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn read_keeps_unknown_keys_of_the_last_export() {
+        let root = temp_dir("unknown_keys");
+        let dir = MetadataDir::new(&root, FileFormat::Yaml);
+        let mut metadata = metadata(&["users"]);
+        metadata["native_queries"] = json!([{ "root_field_name": "q" }]);
+        dir.write(&metadata).unwrap();
+        assert_eq!(
+            dir.read().unwrap()["native_queries"],
+            metadata["native_queries"]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn file_names_cannot_escape_or_collide() {
+        let root = temp_dir("escape");
+        let dir = MetadataDir::new(&root, FileFormat::Json);
+        let mut metadata = metadata(&[]);
+        metadata["sources"][0]["tables"] = json!([
+            { "table": { "schema": "a.b", "name": "c" } },
+            { "table": { "schema": "a", "name": "b.c" } },
+            { "table": { "schema": "..", "name": "../../x" } },
+        ]);
+        let files = dir.write(&metadata).unwrap();
+        assert!(files.iter().all(|f| f.starts_with(&root)));
+        let tables = root.join("sources/default/tables");
+        assert!(tables.join("a%2Eb.c.json").exists());
+        assert!(tables.join("a.b%2Ec.json").exists());
+        assert!(tables.join("%2E%2E.%2E%2E%2F%2E%2E%2Fx.json").exists());
+        assert_eq!(
+            dir.read().unwrap()["sources"][0]["tables"]
+                .as_array()
+                .unwrap()
+                .len(),
+            3
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn names_differing_by_case_get_distinct_files() {
+        let root = temp_dir("clash");
+        let dir = MetadataDir::new(&root, FileFormat::Json);
+        let mut metadata = metadata(&["users", "Users", "users~2"]);
+        let mut second = metadata["sources"][0].clone();
+        second["name"] = json!("Default");
+        metadata["sources"].as_array_mut().unwrap().push(second);
+        let files = dir.write(&metadata).unwrap();
+        let mut relative = files
+            .iter()
+            .map(|f| f.strip_prefix(&root).unwrap().to_str().unwrap().to_string())
+            .filter(|f| f.contains("/tables/") && f.starts_with("sources/default/"))
+            .collect::<Vec<_>>();
+        relative.sort();
+        assert_eq!(
+            relative,
+            [
+                "sources/default/tables/public.Users~2.json",
+                "sources/default/tables/public.users.json",
+                "sources/default/tables/public.users~2~2.json",
+            ]
+        );
+        assert!(root.join("sources/Default~2/source.json").exists());
+        let read = dir.read().unwrap();
+        let mut names = read["sources"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| (s["name"].clone(), s["tables"].as_array().unwrap().len()))
+            .collect::<Vec<_>>();
+        names.sort_by_key(|(name, _)| name.to_string());
+        assert_eq!(names, [(json!("Default"), 3), (json!("default"), 3)]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn names_files_of_other_backends_after_their_identifiers() {
+        let root = temp_dir("backends");
+        let dir = MetadataDir::new(&root, FileFormat::Json);
+        let metadata: Value = serde_json::from_str(include_str!(
+            "../tests/fixtures/metadata/other_backends.json"
+        ))
+        .unwrap();
+        dir.write(&metadata).unwrap();
+        let sources = root.join("sources");
+        assert!(sources.join("analytics/tables/sales.orders.json").exists());
+        assert!(sources
+            .join("analytics/functions/sales.top_customers.json")
+            .exists());
+        assert!(sources.join("chinook/tables/Album.json").exists());
+        assert_eq!(qualified_name(&json!(["main", "Album"])), "main.Album");
+        let read = dir.read().unwrap();
+        let tables = |name: &str| {
+            let sources = read["sources"].as_array().unwrap();
+            let source = sources.iter().find(|s| s["name"] == name).unwrap();
+            source["tables"].as_array().unwrap().clone()
+        };
+        assert!(tables("analytics")
+            .iter()
+            .all(|t| t["table"]["dataset"] == "sales"));
+        assert!(tables("chinook")
+            .iter()
+            .any(|t| t["table"] == json!(["Album"])));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    fn metadata_without(metadata: &Value, keys: &[&str]) -> Value {
+        let mut metadata = metadata.clone();
+        for key in keys {
+            metadata.as_object_mut().unwrap().remove(*key);
+        }
+        metadata
+    }
+}
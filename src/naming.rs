@@ -0,0 +1,204 @@
+use std::collections::{HashMap, HashSet};
+
+use inflector::Inflector;
+
+use crate::metadata::QualifiedTable;
+use crate::types::{CreateRelationship, SQLFKRelationship};
+
+/// Renames generated relationships so that no two relationships on a table share a name.
+///
+/// A relationship keeps its default name (`user`, `orders`) when nothing else on the table
+/// wants it. Otherwise the name is derived from the foreign key columns (`buyer_id` gives
+/// `buyer` and `buyer_orders`, a self-referencing `parent_id` gives `parent` and `children`)
+/// and suffixed with `_2`, `_3`, ... when still ambiguous. `taken` holds the names already
+/// tracked on each table. Relationships are visited in table and constraint order, so the
+/// result does not depend on the order the database returned the foreign keys in.
+pub fn resolve_relationship_names(
+    planned: &mut [(&SQLFKRelationship, CreateRelationship)],
+    mut taken: HashMap<QualifiedTable, HashSet<String>>,
+) {
+    let mut default_counts: HashMap<(QualifiedTable, String), usize> = HashMap::new();
+    for (_, rel) in planned.iter() {
+        *default_counts
+            .entry((rel.table().clone(), rel.name().to_string()))
+            .or_default() += 1;
+    }
+
+    let mut order = (0..planned.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| {
+        let (fk, rel) = &planned[i];
+        (
+            fk.table(),
+            fk.constraint_name.clone(),
+            matches!(rel, CreateRelationship::Array(_)),
+        )
+    });
+
+    for i in order {
+        let (fk, rel) = &mut planned[i];
+        let table = rel.table().clone();
+        let default = rel.name().to_string();
+        let mut candidates = vec![];
+        if !fk.is_self_referencing() && default_counts[&(table.clone(), default.clone())] == 1 {
+            candidates.push(default);
+        }
+        candidates.push(derived_name(fk, rel));
+
+        let names = taken.entry(table).or_default();
+        let name = match candidates.iter().find(|c| !names.contains(*c)) {
+            Some(name) => name.clone(),
+            None => {
+                let base = candidates.last().unwrap();
+                (2..)
+                    .map(|n| format!("{base}_{n}"))
+                    .find(|c| !names.contains(c))
+                    .unwrap()
+            }
+        };
+        names.insert(name.clone());
+        rel.set_name(name);
+    }
+}
+
+fn derived_name(fk: &SQLFKRelationship, rel: &CreateRelationship) -> String {
+    let stem = column_stem(fk);
+    match rel {
+        CreateRelationship::Object(_) => stem.unwrap_or_else(|| {
+            format!(
+                "{}_{}",
+                fk.columns().join("_"),
+                Inflector::to_singular(&fk.ref_table_name)
+            )
+        }),
+        CreateRelationship::Array(_) if fk.is_self_referencing() => "children".to_string(),
+        CreateRelationship::Array(_) => format!(
+            "{}_{}",
+            stem.unwrap_or_else(|| fk.columns().join("_")),
+            Inflector::to_plural(&fk.table_name)
+        ),
+    }
+}
+
+/// `buyer_id` -> `buyer`, `(tenant_id, user_id)` -> `tenant_user`.
+/// `None` when a column has no id suffix, since the name would clash with the column.
+fn column_stem(fk: &SQLFKRelationship) -> Option<String> {
+    fk.column_mapping
+        .keys()
+        .map(|column| {
+            column
+                .strip_suffix("_id")
+                .or_else(|| column.strip_suffix("Id"))
+                .filter(|stem| !stem.is_empty())
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|stems| stems.join("_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::driver::Driver;
+
+    fn fk(table: &str, column: &str, ref_table: &str) -> SQLFKRelationship {
+        serde_json::from_value(json!({
+            "table_schema": "public",
+            "table_name": table,
+            "constraint_name": format!("{table}_{column}_fkey"),
+            "ref_table_schema": "public",
+            "ref_table_name": ref_table,
+            "column_mapping": { column: "id" }
+        }))
+        .unwrap()
+    }
+
+    fn table(name: &str) -> QualifiedTable {
+        QualifiedTable {
+            name: name.to_string(),
+            schema: "public".to_string(),
+        }
+    }
+
+    /// `(table, name)` of the relationships generated for `fks` once resolved
+    fn resolve(
+        fks: &[SQLFKRelationship],
+        taken: HashMap<QualifiedTable, HashSet<String>>,
+    ) -> Vec<(String, String)> {
+        let mut planned = vec![];
+        for fk in fks {
+            let (obj_rel, arr_rel) = fk.get_relationships("default", Driver::Pg);
+            planned.push((fk, obj_rel.into()));
+            planned.push((fk, arr_rel.into()));
+        }
+        resolve_relationship_names(&mut planned, taken);
+        let mut names = planned
+            .iter()
+            .map(|(_, rel)| (rel.table().name.clone(), rel.name().to_string()))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    fn names(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(table, name)| (table.to_string(), name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn two_foreign_keys_to_the_same_table_use_the_columns() {
+        let fks = [
+            fk("orders", "seller_id", "users"),
+            fk("orders", "buyer_id", "users"),
+        ];
+        let expected = names(&[
+            ("orders", "buyer"),
+            ("orders", "seller"),
+            ("users", "buyer_orders"),
+            ("users", "seller_orders"),
+        ]);
+        assert_eq!(resolve(&fks, HashMap::new()), expected);
+        let reversed = [
+            fk("orders", "buyer_id", "users"),
+            fk("orders", "seller_id", "users"),
+        ];
+        assert_eq!(resolve(&reversed, HashMap::new()), expected);
+    }
+
+    #[test]
+    fn self_referencing_foreign_key_gives_parent_and_children() {
+        let fks = [fk("categories", "parent_id", "categories")];
+        assert_eq!(
+            resolve(&fks, HashMap::new()),
+            names(&[("categories", "children"), ("categories", "parent")])
+        );
+    }
+
+    #[test]
+    fn names_already_tracked_are_avoided() {
+        let fks = [fk("orders", "buyer_id", "users")];
+        let taken = HashMap::from([(table("orders"), HashSet::from(["user".to_string()]))]);
+        assert_eq!(
+            resolve(&fks, taken),
+            names(&[("orders", "buyer"), ("users", "orders")])
+        );
+    }
+
+    #[test]
+    fn suffixes_a_number_when_every_candidate_is_taken() {
+        let fks = [fk("orders", "user_id", "users")];
+        let taken = HashMap::from([
+            (table("orders"), HashSet::from(["user".to_string()])),
+            (
+                table("users"),
+                HashSet::from(["orders".to_string(), "user_orders".to_string()]),
+            ),
+        ]);
+        assert_eq!(
+            resolve(&fks, taken),
+            names(&[("orders", "user_2"), ("users", "user_orders_2")])
+        );
+    }
+}
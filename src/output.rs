@@ -0,0 +1,429 @@
+use std::path::PathBuf;
+
+use clap::ArgEnum;
+use console::{measure_text_width, pad_str, style, Alignment};
+use serde_json::{json, Value};
+
+use crate::diff::{Change, DiffEntry};
+use crate::doctor::{CheckStatus, DoctorCheck};
+use crate::error::HasuraUtilsError;
+use crate::source::SourceSummary;
+use crate::types::{InconsistentObject, SQLTable};
+use crate::util::Outcome;
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// JSON for scripts
+    Json,
+    /// Aligned, styled tables for humans
+    Table,
+    /// Newline-separated names for shell pipelines
+    Plain,
+}
+
+/// A command result that can be printed in every `OutputFormat`
+pub trait Render {
+    fn json(&self) -> Value;
+    fn table(&self) -> String;
+    fn plain(&self) -> Vec<String>;
+}
+
+impl OutputFormat {
+    /// Prints `res` to stdout, or the error to stderr exiting with a non-zero code
+    pub fn print<T: Render>(self, res: &Result<T, HasuraUtilsError>) {
+        match res {
+            Ok(value) => match self {
+                OutputFormat::Json => println!("{}", pretty(&value.json())),
+                OutputFormat::Table => println!("{}", value.table()),
+                OutputFormat::Plain => value.plain().iter().for_each(|l| println!("{l}")),
+            },
+            Err(err) => self.exit_with(err),
+        }
+    }
+
+    /// Prints `err` to stderr and exits with its exit code
+    pub fn exit_with(self, err: &HasuraUtilsError) -> ! {
+        match self {
+            OutputFormat::Json => eprintln!("{}", json!({ "error": error_json(err) })),
+            _ => eprintln!("{} {err}", style("error:").red().bold()),
+        }
+        std::process::exit(err.exit_code());
+    }
+
+    /// Prints the result of each source under its name, then exits with the code
+    /// of the first error if any source failed. Unless the command line selected
+    /// `many` sources, the single result is printed like `print`, so that the
+    /// shape of the output does not depend on how many sources a server has.
+    pub fn print_per_source<T: Render>(
+        self,
+        results: &[(String, Result<T, HasuraUtilsError>)],
+        many: bool,
+    ) {
+        if let (false, [(_, res)]) = (many, results) {
+            return self.print(res);
+        }
+        match self {
+            OutputFormat::Json => println!("{}", pretty(&per_source_json(results))),
+            OutputFormat::Table => {
+                for (source, res) in results {
+                    println!("{}", style(format!("source {source}")).bold().underlined());
+                    match res {
+                        Ok(value) => println!("{}\n", value.table()),
+                        Err(err) => eprintln!("{} {err}\n", style("error:").red().bold()),
+                    }
+                }
+            }
+            OutputFormat::Plain => {
+                for (source, res) in results {
+                    match res {
+                        Ok(value) => value.plain().iter().for_each(|l| println!("{source}\t{l}")),
+                        Err(err) => eprintln!("{source}: {err}"),
+                    }
+                }
+            }
+        }
+        if let Some(err) = results.iter().find_map(|(_, res)| res.as_ref().err()) {
+            std::process::exit(err.exit_code());
+        }
+    }
+}
+
+/// `[{"source": <name>, "result": <json>}, ...]`, with `error` instead of `result`
+/// for the sources that failed
+fn per_source_json<T: Render>(results: &[(String, Result<T, HasuraUtilsError>)]) -> Value {
+    let reports = results
+        .iter()
+        .map(|(source, res)| match res {
+            Ok(value) => json!({ "source": source, "result": value.json() }),
+            Err(err) => json!({ "source": source, "error": error_json(err) }),
+        })
+        .collect::<Vec<_>>();
+    json!(reports)
+}
+
+/// Hasura API errors as structured objects, other errors as their message
+fn error_json(err: &HasuraUtilsError) -> Value {
+    match err {
+        HasuraUtilsError::HasuraApi(api_error) => json!(api_error),
+        _ => json!(err.to_string()),
+    }
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_default()
+}
+
+/// Renders `rows` as left aligned columns under bold `headers`
+pub fn aligned(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let widths = headers
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            rows.iter()
+                .filter_map(|row| row.get(i))
+                .map(|cell| measure_text_width(cell))
+                .chain([h.len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    let line = |cells: Vec<String>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| pad_str(cell, width, Alignment::Left, None).to_string())
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    let header = line(
+        headers
+            .iter()
+            .map(|h| style(h).bold().to_string())
+            .collect(),
+    );
+    std::iter::once(header)
+        .chain(rows.iter().map(|row| line(row.clone())))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl Render for Vec<SQLTable> {
+    fn json(&self) -> Value {
+        json!(self)
+    }
+
+    fn table(&self) -> String {
+        let rows = self
+            .iter()
+            .map(|t| {
+                vec![
+                    t.table.schema.clone(),
+                    t.table.name.clone(),
+                    t.kind.to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        aligned(&["schema", "name", "kind"], &rows)
+    }
+
+    fn plain(&self) -> Vec<String> {
+        self.iter().map(|t| t.table.to_string()).collect()
+    }
+}
+
+impl Render for Outcome {
+    fn json(&self) -> Value {
+        match self {
+            Outcome::Applied(body) => body.clone(),
+            Outcome::Planned { summary, request } => json!({
+                "dry_run": true,
+                "summary": summary,
+                "request": request,
+            }),
+        }
+    }
+
+    fn table(&self) -> String {
+        match self {
+            Outcome::Applied(body) => {
+                let rows = applied_messages(body)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, message)| vec![(i + 1).to_string(), message])
+                    .collect::<Vec<_>>();
+                aligned(&["#", "result"], &rows)
+            }
+            Outcome::Planned { summary, request } => format!(
+                "{}\n\n{}\n\n{}",
+                style("Planned changes (dry run)").bold(),
+                summary.join("\n\n"),
+                pretty(request)
+            ),
+        }
+    }
+
+    fn plain(&self) -> Vec<String> {
+        match self {
+            Outcome::Applied(body) => applied_messages(body),
+            Outcome::Planned { summary, .. } => summary.clone(),
+        }
+    }
+}
+
+/// `message` of a metadata API response, one per request for `bulk` responses,
+/// or one per inconsistent object for `replace_metadata` responses
+fn applied_messages(body: &Value) -> Vec<String> {
+    let message = |item: &Value| match item.get("message").and_then(Value::as_str) {
+        Some(message) => message.to_string(),
+        None => item.to_string(),
+    };
+    match body {
+        Value::Array(items) => items.iter().map(message).collect(),
+        item if item.get("is_consistent").is_some() => consistency_messages(item),
+        item => vec![message(item)],
+    }
+}
+
+fn consistency_messages(body: &Value) -> Vec<String> {
+    let objects = body["inconsistent_objects"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    if objects.is_empty() {
+        return vec!["metadata is consistent".to_string()];
+    }
+    objects
+        .iter()
+        .map(|object| {
+            format!(
+                "inconsistent {} {}: {}",
+                object["type"].as_str().unwrap_or("object"),
+                object.get("name").map(Value::to_string).unwrap_or_default(),
+                object["reason"].as_str().unwrap_or_default()
+            )
+        })
+        .collect()
+}
+
+impl Render for Vec<DoctorCheck> {
+    fn json(&self) -> Value {
+        json!(self)
+    }
+
+    fn table(&self) -> String {
+        let rows = self
+            .iter()
+            .map(|check| {
+                let status = match check.status {
+                    CheckStatus::Pass => style("✔ pass").green(),
+                    CheckStatus::Warn => style("! warn").yellow(),
+                    CheckStatus::Fail => style("✘ fail").red(),
+                };
+                vec![
+                    status.bold().to_string(),
+                    check.name.clone(),
+                    style(&check.detail).dim().to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        aligned(&["status", "check", "detail"], &rows)
+    }
+
+    fn plain(&self) -> Vec<String> {
+        self.iter()
+            .map(|check| format!("{} {}", check.status, check.name))
+            .collect()
+    }
+}
+
+/// Files written by `metadata export`
+impl Render for Vec<PathBuf> {
+    fn json(&self) -> Value {
+        json!(self)
+    }
+
+    fn table(&self) -> String {
+        let rows = self
+            .iter()
+            .map(|path| vec![path.display().to_string()])
+            .collect::<Vec<_>>();
+        aligned(&["file"], &rows)
+    }
+
+    fn plain(&self) -> Vec<String> {
+        self.iter().map(|path| path.display().to_string()).collect()
+    }
+}
+
+impl Render for Vec<DiffEntry> {
+    fn json(&self) -> Value {
+        json!(self)
+    }
+
+    fn table(&self) -> String {
+        if self.is_empty() {
+            return style("No differences").green().to_string();
+        }
+        let rows = self
+            .iter()
+            .map(|entry| {
+                let change = match entry.change {
+                    Change::Added => style("+ added").green(),
+                    Change::Removed => style("- removed").red(),
+                    Change::Changed => style("~ changed").yellow(),
+                };
+                vec![
+                    change.bold().to_string(),
+                    entry.object.join(" › "),
+                    style(entry.fields.join(", ")).dim().to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        aligned(&["change", "object", "fields"], &rows)
+    }
+
+    fn plain(&self) -> Vec<String> {
+        self.iter()
+            .map(|entry| {
+                let sign = match entry.change {
+                    Change::Added => '+',
+                    Change::Removed => '-',
+                    Change::Changed => '~',
+                };
+                format!("{sign} {}", entry.object.join("/"))
+            })
+            .collect()
+    }
+}
+
+impl Render for Vec<InconsistentObject> {
+    fn json(&self) -> Value {
+        json!(self)
+    }
+
+    fn table(&self) -> String {
+        if self.is_empty() {
+            return style("Metadata is consistent").green().to_string();
+        }
+        let rows = self
+            .iter()
+            .map(|object| {
+                vec![
+                    object.source().unwrap_or("-").to_string(),
+                    object.r#type.clone(),
+                    object.display_name(),
+                    style(&object.reason).red().to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        aligned(&["source", "type", "name", "reason"], &rows)
+    }
+
+    fn plain(&self) -> Vec<String> {
+        self.iter()
+            .map(|object| {
+                format!(
+                    "{} {}: {}",
+                    object.r#type,
+                    object.display_name(),
+                    object.reason
+                )
+            })
+            .collect()
+    }
+}
+
+impl Render for Vec<SourceSummary> {
+    fn json(&self) -> Value {
+        json!(self)
+    }
+
+    fn table(&self) -> String {
+        let rows = self
+            .iter()
+            .map(|source| {
+                vec![
+                    source.name.clone(),
+                    source.kind.clone(),
+                    source.database.clone(),
+                    source.read_replicas.to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        aligned(&["name", "kind", "database", "read replicas"], &rows)
+    }
+
+    fn plain(&self) -> Vec<String> {
+        self.iter().map(|source| source.name.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::OtherError;
+
+    #[test]
+    fn per_source_json_lists_every_source() {
+        let results = vec![
+            (
+                "default".to_string(),
+                Ok(Outcome::Applied(json!({ "message": "success" }))),
+            ),
+            (
+                "reporting".to_string(),
+                Err(OtherError("Driver does not support functions").into()),
+            ),
+        ];
+        assert_eq!(
+            per_source_json(&results),
+            json!([
+                { "source": "default", "result": { "message": "success" } },
+                { "source": "reporting", "error": "Driver does not support functions" },
+            ])
+        );
+        assert_eq!(per_source_json(&results[..1]).as_array().unwrap().len(), 1);
+    }
+}
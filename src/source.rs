@@ -0,0 +1,406 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::cli::ConnectionArgs;
+use crate::doctor::DoctorCheck;
+use crate::driver::Driver;
+use crate::error::{HasuraUtilsError, OtherError};
+use crate::metadata::{ConnectionInfo, SourceConfiguration};
+use crate::types::{AddSource, AddSourceArgs, DropSource, DropSourceArgs, UpdateSource};
+use crate::util::{HasuraUtils, Outcome};
+
+/// A source of the metadata, as listed by `source list`
+#[derive(Debug, Serialize)]
+pub struct SourceSummary {
+    pub name: String,
+    pub kind: String,
+    /// Env var or URL (without password) of the database
+    pub database: String,
+    pub read_replicas: usize,
+}
+
+impl HasuraUtils {
+    pub async fn add_source(
+        &self,
+        name: &str,
+        connection: &ConnectionArgs,
+    ) -> Result<Outcome, HasuraUtilsError> {
+        let mut configuration = SourceConfiguration::default();
+        configure(&mut configuration, connection, self.env.driver);
+        let summary = vec![format!(
+            "add source {name} ({})",
+            describe_database(&configuration)
+        )];
+        let configuration = serde_json::to_value(&configuration)?;
+        let body = AddSource::new(
+            self.env.driver,
+            AddSourceArgs {
+                name,
+                configuration: &configuration,
+            },
+        );
+        self.send_metadata(summary, &body).await
+    }
+
+    /// Applies `connection` over the current configuration of the source. The result
+    /// is merged into the exported configuration, so that settings this tool does not
+    /// model are sent back as they are.
+    pub async fn update_source(
+        &self,
+        name: &str,
+        connection: &ConnectionArgs,
+    ) -> Result<Outcome, HasuraUtilsError> {
+        let (source, driver) = self.exported_source(name).await?;
+        let mut exported = match &source["configuration"] {
+            Value::Null => json!({}),
+            configuration => configuration.clone(),
+        };
+        let mut configuration: SourceConfiguration = serde_json::from_value(exported.clone())?;
+        configure(&mut configuration, connection, driver);
+        merge(&mut exported, serde_json::to_value(&configuration)?);
+        let summary = vec![format!(
+            "update source {name} ({})",
+            describe_database(&configuration)
+        )];
+        let body = UpdateSource::new(
+            driver,
+            AddSourceArgs {
+                name,
+                configuration: &exported,
+            },
+        );
+        self.send_metadata(summary, &body).await
+    }
+
+    pub async fn remove_source(
+        &self,
+        name: &str,
+        cascade: bool,
+    ) -> Result<Outcome, HasuraUtilsError> {
+        let summary = vec![match cascade {
+            true => format!("drop source {name} (cascade)"),
+            false => format!("drop source {name}"),
+        }];
+        let (_, driver) = self.exported_source(name).await?;
+        let body = DropSource::new(driver, DropSourceArgs { name, cascade });
+        self.send_metadata(summary, &body).await
+    }
+
+    /// The source named `name` as exported, with the driver of its kind
+    async fn exported_source(&self, name: &str) -> Result<(Value, Driver), HasuraUtilsError> {
+        let metadata = self.export_metadata().await?;
+        let source = metadata["sources"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|s| s["name"] == name)
+            .cloned()
+            .ok_or(OtherError("Source is not in the metadata"))?;
+        let driver = source_driver(&source)?;
+        Ok((source, driver))
+    }
+
+    pub async fn list_sources(&self) -> Result<Vec<SourceSummary>, HasuraUtilsError> {
+        let metadata = self.get_metadata().await?;
+        Ok(metadata
+            .sources()
+            .iter()
+            .map(|source| {
+                let configuration = source.configuration.clone().unwrap_or_default();
+                SourceSummary {
+                    name: source.name.clone(),
+                    kind: source.kind().to_string(),
+                    database: describe_database(&configuration),
+                    read_replicas: configuration.read_replicas.map(|r| r.len()).unwrap_or(0),
+                }
+            })
+            .collect())
+    }
+
+    /// Runs `SELECT 1` on every source of the metadata
+    pub async fn test_sources(&self) -> Result<Vec<DoctorCheck>, HasuraUtilsError> {
+        let metadata = self.get_metadata().await?;
+        Ok(self.ping_sources(metadata.sources()).await)
+    }
+}
+
+/// Applies the settings given in `connection` to `configuration`, keeping the others.
+/// Read replicas are replaced when any is given.
+fn configure(configuration: &mut SourceConfiguration, connection: &ConnectionArgs, driver: Driver) {
+    let info = configuration
+        .connection_info
+        .get_or_insert_with(ConnectionInfo::default);
+    if let Some(url) = database_url(&connection.database_url, &connection.database_url_env) {
+        set_database_url(info, url, driver);
+    }
+    if let Some(level) = &connection.isolation_level {
+        info.isolation_level = Some(level.to_string());
+    }
+    let pool = [
+        ("max_connections", connection.max_connections),
+        ("idle_timeout", connection.idle_timeout),
+        ("connection_lifetime", connection.connection_lifetime),
+        ("pool_timeout", connection.pool_timeout),
+        ("retries", connection.retries),
+    ];
+    if pool.iter().any(|(_, value)| value.is_some()) {
+        let mut settings = match info.pool_settings.take() {
+            Some(Value::Object(settings)) => settings,
+            _ => Map::new(),
+        };
+        for (key, value) in pool {
+            if let Some(value) = value {
+                settings.insert(key.to_string(), json!(value));
+            }
+        }
+        info.pool_settings = Some(Value::Object(settings));
+    }
+
+    let replicas = connection
+        .read_replica_url
+        .iter()
+        .map(|url| database_url(&Some(url.to_string()), &None))
+        .chain(
+            connection
+                .read_replica_env
+                .iter()
+                .map(|var| database_url(&None, &Some(var.to_string()))),
+        )
+        .flatten()
+        .map(|url| {
+            let mut info = ConnectionInfo::default();
+            set_database_url(&mut info, url, driver);
+            info
+        })
+        .collect::<Vec<_>>();
+    if !replicas.is_empty() {
+        configuration.read_replicas = Some(replicas);
+    }
+}
+
+/// Overwrites the values of `target` with those of `patch`, merging objects key by
+/// key so that keys missing from `patch` are kept
+fn merge(target: &mut Value, patch: Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                match target.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
+
+/// Driver of the kind of an exported source, `postgres` when it has none
+fn source_driver(source: &Value) -> Result<Driver, HasuraUtilsError> {
+    Driver::require_kind(source["kind"].as_str().unwrap_or("postgres"))
+}
+
+/// MSSQL sources take an ODBC `connection_string` instead of a `database_url`
+fn set_database_url(info: &mut ConnectionInfo, url: Value, driver: Driver) {
+    match driver {
+        Driver::Mssql => info.connection_string = Some(url),
+        Driver::Pg | Driver::Citus | Driver::Cockroach => info.database_url = Some(url),
+    }
+}
+
+/// A URL, or `{"from_env": "VAR"}` for an env var of the Hasura server
+fn database_url(url: &Option<String>, env: &Option<String>) -> Option<Value> {
+    match (url, env) {
+        (Some(url), _) => Some(Value::String(url.to_string())),
+        (None, Some(var)) => Some(json!({ "from_env": var })),
+        (None, None) => None,
+    }
+}
+
+/// `env VAR`, or the database URL with its password hidden
+fn describe_database(configuration: &SourceConfiguration) -> String {
+    let info = configuration.connection_info.as_ref();
+    let url = info.and_then(|i| i.database_url.as_ref().or(i.connection_string.as_ref()));
+    match url {
+        Some(Value::String(url)) => redact_password(url),
+        Some(url) => match url["from_env"].as_str() {
+            Some(var) => format!("env {var}"),
+            None => url.to_string(),
+        },
+        None => "-".to_string(),
+    }
+}
+
+fn redact_password(url: &str) -> String {
+    let (scheme, rest) = match url.split_once("://") {
+        Some(parts) => parts,
+        None => return url.to_string(),
+    };
+    match rest.rsplit_once('@') {
+        Some((credentials, host)) if credentials.contains(':') => {
+            let user = credentials.split(':').next().unwrap_or_default();
+            format!("{scheme}://{user}:***@{host}")
+        }
+        _ => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection() -> ConnectionArgs {
+        ConnectionArgs {
+            database_url: None,
+            database_url_env: None,
+            max_connections: None,
+            idle_timeout: None,
+            connection_lifetime: None,
+            pool_timeout: None,
+            retries: None,
+            isolation_level: None,
+            read_replica_url: vec![],
+            read_replica_env: vec![],
+        }
+    }
+
+    #[test]
+    fn configure_keeps_settings_that_are_not_given() {
+        let mut configuration: SourceConfiguration = serde_json::from_value(json!({
+            "connection_info": {
+                "database_url": { "from_env": "PG_DATABASE_URL" },
+                "isolation_level": "read-committed",
+                "pool_settings": { "max_connections": 50, "idle_timeout": 180 }
+            },
+            "read_replicas": [{ "database_url": "postgres://replica/app" }]
+        }))
+        .unwrap();
+        configure(
+            &mut configuration,
+            &ConnectionArgs {
+                max_connections: Some(20),
+                ..connection()
+            },
+            Driver::Pg,
+        );
+        assert_eq!(
+            serde_json::to_value(&configuration).unwrap(),
+            json!({
+                "connection_info": {
+                    "database_url": { "from_env": "PG_DATABASE_URL" },
+                    "isolation_level": "read-committed",
+                    "pool_settings": { "max_connections": 20, "idle_timeout": 180 }
+                },
+                "read_replicas": [{ "database_url": "postgres://replica/app" }]
+            })
+        );
+    }
+
+    #[test]
+    fn configure_replaces_database_and_read_replicas() {
+        let mut configuration = SourceConfiguration::default();
+        configure(
+            &mut configuration,
+            &ConnectionArgs {
+                database_url_env: Some("PG_DATABASE_URL".to_string()),
+                read_replica_url: vec!["postgres://replica/app".to_string()],
+                read_replica_env: vec!["PG_REPLICA_URL".to_string()],
+                ..connection()
+            },
+            Driver::Pg,
+        );
+        assert_eq!(
+            serde_json::to_value(&configuration).unwrap(),
+            json!({
+                "connection_info": { "database_url": { "from_env": "PG_DATABASE_URL" } },
+                "read_replicas": [
+                    { "database_url": "postgres://replica/app" },
+                    { "database_url": { "from_env": "PG_REPLICA_URL" } }
+                ]
+            })
+        );
+        assert_eq!(describe_database(&configuration), "env PG_DATABASE_URL");
+    }
+
+    #[test]
+    fn configure_sets_connection_string_for_mssql() {
+        let mut configuration = SourceConfiguration::default();
+        configure(
+            &mut configuration,
+            &ConnectionArgs {
+                database_url_env: Some("MSSQL_CONNECTION_STRING".to_string()),
+                ..connection()
+            },
+            Driver::Mssql,
+        );
+        assert_eq!(
+            serde_json::to_value(&configuration).unwrap(),
+            json!({
+                "connection_info": {
+                    "connection_string": { "from_env": "MSSQL_CONNECTION_STRING" }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn merge_keeps_keys_missing_from_the_patch() {
+        let mut exported = json!({
+            "connection_info": {
+                "database_url": "postgres://db/app",
+                "pool_settings": { "max_connections": 50 }
+            },
+            "connection_template": { "template": "{{ return \"primary\" }}" },
+            "read_replicas": [{ "database_url": "postgres://old/app" }]
+        });
+        merge(
+            &mut exported,
+            json!({
+                "connection_info": {
+                    "database_url": { "from_env": "PG_DATABASE_URL" },
+                    "pool_settings": { "max_connections": 20 }
+                },
+                "read_replicas": [{ "database_url": "postgres://new/app" }]
+            }),
+        );
+        assert_eq!(
+            exported,
+            json!({
+                "connection_info": {
+                    "database_url": { "from_env": "PG_DATABASE_URL" },
+                    "pool_settings": { "max_connections": 20 }
+                },
+                "connection_template": { "template": "{{ return \"primary\" }}" },
+                "read_replicas": [{ "database_url": "postgres://new/app" }]
+            })
+        );
+    }
+
+    #[test]
+    fn source_driver_follows_the_kind() {
+        let driver = |source| source_driver(&source).map_err(|err| err.to_string());
+        assert_eq!(driver(json!({ "name": "default" })), Ok(Driver::Pg));
+        assert_eq!(driver(json!({ "kind": "mssql" })), Ok(Driver::Mssql));
+        assert_eq!(
+            driver(json!({ "kind": "cockroach" })),
+            Ok(Driver::Cockroach)
+        );
+        assert_eq!(
+            driver(json!({ "kind": "bigquery" })),
+            Err("unsupported kind bigquery".to_string())
+        );
+    }
+
+    #[test]
+    fn redacts_password_of_database_url() {
+        assert_eq!(
+            redact_password("postgres://app:s3cr@t@db:5432/app"),
+            "postgres://app:***@db:5432/app"
+        );
+        assert_eq!(
+            redact_password("postgres://db:5432/app"),
+            "postgres://db:5432/app"
+        );
+    }
+}
@@ -1,21 +1,47 @@
-pub fn get_all_tables_sql() -> &'static str {
-    r#"
+use crate::driver::Driver;
+
+pub fn get_all_tables_sql(driver: Driver) -> &'static str {
+    match driver {
+        Driver::Pg => PG_ALL_TABLES,
+        Driver::Citus => CITUS_ALL_TABLES,
+        Driver::Cockroach => COCKROACH_ALL_TABLES,
+        Driver::Mssql => MSSQL_ALL_TABLES,
+    }
+}
+
+pub fn get_all_fk_relationships(driver: Driver) -> &'static str {
+    match driver {
+        Driver::Pg | Driver::Citus => PG_ALL_FK_RELATIONSHIPS,
+        Driver::Cockroach => COCKROACH_ALL_FK_RELATIONSHIPS,
+        Driver::Mssql => MSSQL_ALL_FK_RELATIONSHIPS,
+    }
+}
+
+const PG_ALL_TABLES: &str = r#"
     SELECT
 	COALESCE(json_agg(row_to_json(info)), '[]'::JSON)
 FROM (
 	SELECT
-		table_name as name,
-		table_schema as schema
+		c.relname :: text as name,
+		n.nspname :: text as schema,
+		CASE c.relkind
+			WHEN 'v' THEN 'view'
+			WHEN 'm' THEN 'materialized_view'
+			WHEN 'f' THEN 'foreign'
+			ELSE 'table'
+		END as kind
 	FROM
-		information_schema.tables
+		pg_class c
+		JOIN pg_namespace n ON c.relnamespace = n.oid
 	WHERE
-		table_type = 'BASE TABLE'
-		AND table_schema NOT IN('pg_catalog', 'information_schema', 'hdb_catalog')) AS info;
-    "#
-}
+		c.relkind IN('r', 'p', 'v', 'm', 'f')
+		AND NOT c.relispartition
+		AND n.nspname NOT IN('pg_catalog', 'information_schema', 'hdb_catalog')
+		AND n.nspname NOT LIKE 'pg_toast%'
+		AND n.nspname NOT LIKE 'pg_temp%') AS info;
+    "#;
 
-pub fn get_all_fk_relationships() -> &'static str {
-    r#"SELECT
+const PG_ALL_FK_RELATIONSHIPS: &str = r#"SELECT
 COALESCE(json_agg(row_to_json(info)), '[]'::JSON)
 FROM (
     SELECT
@@ -58,5 +84,277 @@ FROM (
       q.table_schema,
       q.table_name,
       q.constraint_name
+  ) AS info;"#;
+
+/// Like `PG_ALL_TABLES`, without the catalogs of the extension nor the shards of
+/// distributed and reference tables (`<table>_<shardid>`). Citus 11+ hides shards
+/// from `pg_class` by default, older versions list them on nodes holding shards.
+const CITUS_ALL_TABLES: &str = r#"SELECT
+COALESCE(json_agg(row_to_json(info)), '[]'::JSON)
+FROM (
+    SELECT
+    c.relname :: text AS name,
+    n.nspname :: text AS schema,
+    CASE c.relkind
+      WHEN 'v' THEN 'view'
+      WHEN 'm' THEN 'materialized_view'
+      WHEN 'f' THEN 'foreign'
+      ELSE 'table'
+    END AS kind
+    FROM
+      pg_class c
+      JOIN pg_namespace n ON c.relnamespace = n.oid
+    WHERE
+      c.relkind IN('r', 'p', 'v', 'm', 'f')
+      AND NOT c.relispartition
+      AND n.nspname NOT IN('pg_catalog', 'information_schema', 'hdb_catalog', 'citus', 'citus_internal', 'columnar', 'columnar_internal')
+      AND n.nspname NOT LIKE 'pg_toast%'
+      AND n.nspname NOT LIKE 'pg_temp%'
+      AND NOT EXISTS (
+        SELECT 1
+        FROM pg_dist_shard s
+          JOIN pg_class l ON l.oid = s.logicalrelid
+        WHERE l.relnamespace = c.relnamespace
+          AND c.relname = l.relname || '_' || s.shardid
+      )
+  ) AS info;"#;
+
+/// CockroachDB emulates `pg_catalog` only partially, so its `information_schema`
+/// is used instead
+const COCKROACH_ALL_TABLES: &str = r#"SELECT
+COALESCE(json_agg(row_to_json(info)), '[]'::JSON)
+FROM (
+    SELECT
+    t.table_name AS name,
+    t.table_schema AS schema,
+    CASE t.table_type
+      WHEN 'VIEW' THEN 'view'
+      WHEN 'MATERIALIZED VIEW' THEN 'materialized_view'
+      ELSE 'table'
+    END AS kind
+    FROM
+      information_schema.tables t
+    WHERE
+      t.table_type IN('BASE TABLE', 'VIEW', 'MATERIALIZED VIEW')
+      AND t.table_schema NOT IN('pg_catalog', 'information_schema', 'crdb_internal', 'pg_extension', 'hdb_catalog')
+  ) AS info;"#;
+
+/// Constraint names are only unique per table on CockroachDB, hence the joins on
+/// `table_name` and `referenced_table_name`
+const COCKROACH_ALL_FK_RELATIONSHIPS: &str = r#"SELECT
+COALESCE(json_agg(row_to_json(info)), '[]'::JSON)
+FROM (
+    SELECT
+    kcu.table_schema AS table_schema,
+    kcu.table_name AS table_name,
+    rc.constraint_name AS constraint_name,
+    min(rkcu.table_schema) AS ref_table_schema,
+    min(rkcu.table_name) AS ref_table_name,
+    json_object_agg(kcu.column_name, rkcu.column_name) AS column_mapping
+    FROM
+      information_schema.referential_constraints rc
+      JOIN information_schema.key_column_usage kcu ON kcu.constraint_schema = rc.constraint_schema
+      AND kcu.constraint_name = rc.constraint_name
+      AND kcu.table_name = rc.table_name
+      JOIN information_schema.key_column_usage rkcu ON rkcu.constraint_schema = rc.unique_constraint_schema
+      AND rkcu.constraint_name = rc.unique_constraint_name
+      AND rkcu.table_name = rc.referenced_table_name
+      AND rkcu.ordinal_position = kcu.position_in_unique_constraint
+    WHERE
+      kcu.table_schema NOT IN('pg_catalog', 'information_schema', 'crdb_internal', 'pg_extension', 'hdb_catalog')
+    GROUP BY
+      kcu.table_schema,
+      kcu.table_name,
+      rc.constraint_name
+  ) AS info;"#;
+
+/// `FOR JSON` returns no row for an empty result, hence the `ISNULL`
+const MSSQL_ALL_TABLES: &str = r#"SELECT ISNULL((
+    SELECT
+    t.[name],
+    t.[schema],
+    t.[kind]
+    FROM
+    (
+      SELECT name, SCHEMA_NAME(schema_id) AS [schema], 'table' AS kind
+      FROM sys.tables
+      WHERE is_ms_shipped = 0
+      UNION ALL
+      SELECT name, SCHEMA_NAME(schema_id) AS [schema], 'view' AS kind
+      FROM sys.views
+      WHERE is_ms_shipped = 0
+    ) t
+    WHERE t.[schema] NOT IN('sys', 'INFORMATION_SCHEMA')
+    FOR JSON PATH
+  ), '[]');"#;
+
+/// `column_mapping` is built as a string because `FOR JSON` cannot produce
+/// objects with dynamic keys
+const MSSQL_ALL_FK_RELATIONSHIPS: &str = r#"SELECT ISNULL((
+    SELECT
+    SCHEMA_NAME(t.schema_id) AS table_schema,
+    t.name AS table_name,
+    fk.name AS constraint_name,
+    SCHEMA_NAME(rt.schema_id) AS ref_table_schema,
+    rt.name AS ref_table_name,
+    JSON_QUERY((
+      SELECT
+      '{' + STRING_AGG(
+        '"' + STRING_ESCAPE(c.name, 'json') + '":"' + STRING_ESCAPE(rc.name, 'json') + '"',
+        ','
+      ) + '}'
+      FROM
+        sys.foreign_key_columns fkc
+        JOIN sys.columns c ON c.object_id = fkc.parent_object_id
+        AND c.column_id = fkc.parent_column_id
+        JOIN sys.columns rc ON rc.object_id = fkc.referenced_object_id
+        AND rc.column_id = fkc.referenced_column_id
+      WHERE
+        fkc.constraint_object_id = fk.object_id
+    )) AS column_mapping
+    FROM
+      sys.foreign_keys fk
+      JOIN sys.tables t ON fk.parent_object_id = t.object_id
+      JOIN sys.tables rt ON fk.referenced_object_id = rt.object_id
+    WHERE
+      t.is_ms_shipped = 0
+    FOR JSON PATH
+  ), '[]');"#;
+
+pub fn get_all_functions_sql() -> &'static str {
+    r#"SELECT
+COALESCE(json_agg(row_to_json(info)), '[]'::JSON)
+FROM (
+    SELECT
+    json_build_object('name', p.proname :: text, 'schema', pn.nspname :: text) AS function,
+    json_build_object('name', rt.relname :: text, 'schema', rtn.nspname :: text) AS return_table,
+    EXISTS (
+      SELECT 1 FROM pg_proc o
+      WHERE o.pronamespace = p.pronamespace AND o.proname = p.proname AND o.oid <> p.oid
+    ) AS overloaded
+    FROM
+      pg_proc p
+      JOIN pg_namespace pn ON p.pronamespace = pn.oid
+      JOIN pg_type t ON p.prorettype = t.oid
+      JOIN pg_class rt ON t.typrelid = rt.oid
+      JOIN pg_namespace rtn ON rt.relnamespace = rtn.oid
+    WHERE
+      p.proretset
+      AND t.typtype = 'c' :: "char"
+      AND pn.nspname NOT IN('pg_catalog', 'information_schema', 'hdb_catalog')
+  ) AS info;"#
+}
+
+pub fn get_all_function_names_sql() -> &'static str {
+    r#"SELECT
+COALESCE(json_agg(row_to_json(info)), '[]'::JSON)
+FROM (
+    SELECT DISTINCT
+    p.proname :: text AS name,
+    pn.nspname :: text AS schema
+    FROM
+      pg_proc p
+      JOIN pg_namespace pn ON p.pronamespace = pn.oid
+    WHERE
+      pn.nspname NOT IN('pg_catalog', 'information_schema', 'hdb_catalog')
   ) AS info;"#
 }
+
+#[cfg(test)]
+mod tests {
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+
+    use crate::types::{RunSQLReponse, SQLFKRelationship, SQLTable, TableKind};
+
+    /// Parses the single value returned by `run_sql` for `column`, as Hasura sends it
+    fn parse<T: Default + DeserializeOwned>(column: &str, value: &str) -> T {
+        let response = json!({ "result_type": "TuplesOk", "result": [[column], [value]] });
+        serde_json::from_value::<RunSQLReponse>(response)
+            .unwrap()
+            .into_inner()
+            .unwrap()
+    }
+
+    fn tables(tables: &[SQLTable]) -> Vec<(String, TableKind)> {
+        tables
+            .iter()
+            .map(|t| (format!("{}.{}", t.table.schema, t.table.name), t.kind))
+            .collect()
+    }
+
+    #[test]
+    fn parses_the_tables_of_postgres_and_citus() {
+        let parsed: Vec<SQLTable> = parse(
+            "coalesce",
+            r#"[{"name":"active_users","schema":"public","kind":"view"}, {"name":"users","schema":"public","kind":"table"}, {"name":"stats","schema":"public","kind":"materialized_view"}]"#,
+        );
+        assert_eq!(
+            tables(&parsed),
+            [
+                ("public.active_users".to_string(), TableKind::View),
+                ("public.users".to_string(), TableKind::Table),
+                ("public.stats".to_string(), TableKind::MaterializedView),
+            ]
+        );
+        assert!(parse::<Vec<SQLTable>>("coalesce", "[]").is_empty());
+    }
+
+    #[test]
+    fn parses_the_tables_of_mssql() {
+        let parsed: Vec<SQLTable> = parse(
+            "",
+            r#"[{"name":"orders","schema":"dbo","kind":"table"},{"name":"active_users","schema":"sales","kind":"view"}]"#,
+        );
+        assert_eq!(
+            tables(&parsed),
+            [
+                ("dbo.orders".to_string(), TableKind::Table),
+                ("sales.active_users".to_string(), TableKind::View),
+            ]
+        );
+        assert!(parse::<Vec<SQLTable>>("", "[]").is_empty());
+    }
+
+    #[test]
+    fn parses_the_foreign_keys_of_postgres_and_citus() {
+        let parsed: Vec<SQLFKRelationship> = parse(
+            "coalesce",
+            r#"[{"table_schema":"public","table_name":"orders","constraint_name":"orders_user_id_Org_fkey","ref_table_schema":"public","ref_table_name":"users","column_mapping":{ "user_id" : "id", "Org" : "Org" }}]"#,
+        );
+        assert_eq!(
+            parsed[0].describe(),
+            "public.orders.(Org, user_id) --> public.users.(Org, id)"
+        );
+    }
+
+    #[test]
+    fn parses_the_foreign_keys_of_cockroach() {
+        let parsed: Vec<SQLFKRelationship> = parse(
+            "coalesce",
+            r#"[{"column_mapping": {"user_id": "id"}, "constraint_name": "orders_user_id_fkey", "ref_table_name": "users", "ref_table_schema": "public", "table_name": "orders", "table_schema": "public"}]"#,
+        );
+        assert_eq!(
+            parsed[0].describe(),
+            "public.orders.user_id --> public.users.id"
+        );
+    }
+
+    /// `JSON_QUERY` embeds the `column_mapping` built by `STRING_AGG` as an object,
+    /// with the names escaped by `STRING_ESCAPE`
+    #[test]
+    fn parses_the_string_built_column_mapping_of_mssql() {
+        let parsed: Vec<SQLFKRelationship> = parse(
+            "",
+            r#"[{"table_schema":"dbo","table_name":"orders","constraint_name":"FK_orders_users","ref_table_schema":"dbo","ref_table_name":"users","column_mapping":{"user_id":"id","org \"code\"":"org\/code"}}]"#,
+        );
+        assert_eq!(parsed[0].constraint_name, "FK_orders_users");
+        assert_eq!(
+            parsed[0].column_mapping.iter().collect::<Vec<_>>(),
+            [
+                (&"org \"code\"".to_string(), &"org/code".to_string()),
+                (&"user_id".to_string(), &"id".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::HashSet;
+
+use crate::driver::Driver;
+use crate::error::HasuraUtilsError;
+use crate::filter::ObjectFilter;
+use crate::metadata::{Metadata, QualifiedFunction, QualifiedTable};
+use crate::types::{
+    overloaded_functions, BulkRequest, DropRelationship, DropRelationshipArgs, MetadataChange,
+    SQLFKRelationship, SQLFunction, SQLTable, TableKind, TrackFunction, TrackFunctionArgs,
+    TrackTable, TrackTableArgs, UntrackFunction, UntrackTable, UntrackTableArgs,
+};
+use crate::util::{HasuraUtils, Outcome};
+
+/// Everything the database has that could be tracked
+pub struct Catalog {
+    /// Relations of every kind, used to find tracked tables that were dropped
+    pub tables: Vec<SQLTable>,
+    pub relationships: Vec<SQLFKRelationship>,
+    /// Functions returning `SETOF <table>`
+    pub functions: Vec<SQLFunction>,
+    /// Every user defined function, used to find tracked functions that were dropped
+    pub function_names: Vec<QualifiedFunction>,
+}
+
+/// Objects to untrack and track to bring the metadata of a source in line with the database
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    /// Relationships whose foreign key was dropped, as `(table, relationship name)`
+    pub drop_relationships: Vec<(QualifiedTable, String)>,
+    pub untrack_tables: Vec<QualifiedTable>,
+    pub untrack_functions: Vec<QualifiedFunction>,
+    pub track_tables: Vec<SQLTable>,
+    /// Foreign keys between tables that are tracked once the plan is applied
+    pub relationships: Vec<SQLFKRelationship>,
+    pub track_functions: Vec<QualifiedFunction>,
+    /// Overloaded functions, which Hasura cannot track
+    pub skipped_functions: Vec<QualifiedFunction>,
+}
+
+impl SyncPlan {
+    /// Diffs `catalog` against the metadata of `source`. Only objects selected by
+    /// `filter` are considered, and only tables of `kinds` are tracked.
+    pub fn new(
+        metadata: &Metadata,
+        source: &str,
+        catalog: Catalog,
+        kinds: &[TableKind],
+        filter: &ObjectFilter,
+    ) -> Self {
+        let live_tables = catalog
+            .tables
+            .iter()
+            .map(|t| &t.table)
+            .collect::<HashSet<_>>();
+        let tracked_tables = metadata
+            .get_tracked_tables(source)
+            .into_iter()
+            .filter(|t| filter.matches(t))
+            .collect::<Vec<_>>();
+        let untrack_tables = tracked_tables
+            .iter()
+            .filter(|t| !live_tables.contains(*t))
+            .map(|&t| t.clone())
+            .collect::<Vec<_>>();
+        let drop_relationships = metadata
+            .get_dangling_relationships(&catalog.relationships, source)
+            .into_iter()
+            .filter(|(table, _)| filter.matches(table) && !untrack_tables.contains(table))
+            .map(|(table, name)| (table.clone(), name.to_string()))
+            .collect();
+
+        let live_functions = catalog.function_names.iter().collect::<HashSet<_>>();
+        let untrack_functions = metadata
+            .get_tracked_functions(source)
+            .into_iter()
+            .filter(|f| filter.matches(f) && !live_functions.contains(f))
+            .cloned()
+            .collect();
+
+        let track_tables = catalog
+            .tables
+            .into_iter()
+            .filter(|t| kinds.contains(&t.kind) && filter.matches(&t.table))
+            .filter(|t| !tracked_tables.contains(&&t.table))
+            .collect::<Vec<_>>();
+
+        let tracked_after = tracked_tables
+            .into_iter()
+            .filter(|t| !untrack_tables.contains(t))
+            .chain(track_tables.iter().map(|t| &t.table))
+            .cloned()
+            .collect::<HashSet<_>>();
+
+        let relationships = catalog
+            .relationships
+            .into_iter()
+            .filter(|rel| {
+                tracked_after.contains(&rel.table()) && tracked_after.contains(&rel.ref_table())
+            })
+            .collect();
+
+        let functions = catalog
+            .functions
+            .into_iter()
+            .filter(|f| filter.matches(&f.function))
+            .collect::<Vec<_>>();
+        let skipped_functions = overloaded_functions(&functions);
+        let track_functions = functions
+            .into_iter()
+            .filter(|f| !f.overloaded && tracked_after.contains(&f.return_table))
+            .filter(|f| !metadata.is_function_tracked(&f.function, source))
+            .map(|f| f.function)
+            .collect();
+
+        Self {
+            drop_relationships,
+            untrack_tables,
+            untrack_functions,
+            track_tables,
+            relationships,
+            track_functions,
+            skipped_functions,
+        }
+    }
+
+    /// Dry-run summary and requests of the plan: dangling relationships are dropped
+    /// first, then dangling functions are untracked before the tables they may return,
+    /// then new tables, relationships and functions are tracked
+    pub fn changes<'a>(
+        &'a self,
+        metadata: &'a Metadata,
+        source: &'a str,
+        driver: Driver,
+        cascade: bool,
+    ) -> (Vec<String>, Vec<MetadataChange<'a>>) {
+        let mut changes: Vec<MetadataChange> = vec![];
+        let mut summary = vec![];
+        let suffix = if cascade { " (cascade)" } else { "" };
+        for (table, relationship) in &self.drop_relationships {
+            summary.push(format!(
+                "drop dangling relationship {relationship} on {table}{suffix}"
+            ));
+            changes.push(MetadataChange::DropRelationship(DropRelationship::new(
+                driver,
+                DropRelationshipArgs {
+                    source,
+                    table,
+                    relationship,
+                    cascade,
+                },
+            )));
+        }
+        for function in &self.untrack_functions {
+            summary.push(format!("untrack dangling function {function}"));
+            changes.push(MetadataChange::UntrackFunction(UntrackFunction::new(
+                driver,
+                TrackFunctionArgs { source, function },
+            )));
+        }
+        for table in &self.untrack_tables {
+            summary.push(format!("untrack dangling table {table}{suffix}"));
+            changes.push(MetadataChange::UntrackTable(UntrackTable::new(
+                driver,
+                UntrackTableArgs {
+                    source,
+                    table,
+                    cascade,
+                },
+            )));
+        }
+        for t in &self.track_tables {
+            summary.push(format!("track {} {}", t.kind, t.table));
+            changes.push(MetadataChange::TrackTable(TrackTable::new(
+                driver,
+                TrackTableArgs {
+                    source,
+                    table: &t.table,
+                },
+            )));
+        }
+        for (fk, rel) in metadata.get_untracked_relationships(&self.relationships, source, driver) {
+            summary.push(rel.summary(fk));
+            changes.push(MetadataChange::Relationship(Box::new(rel)));
+        }
+        for function in &self.track_functions {
+            summary.push(format!("track function {function}"));
+            changes.push(MetadataChange::TrackFunction(TrackFunction::new(
+                driver,
+                TrackFunctionArgs { source, function },
+            )));
+        }
+        for function in &self.skipped_functions {
+            summary.push(format!("skip overloaded function {function}"));
+        }
+
+        (summary, changes)
+    }
+}
+
+impl HasuraUtils {
+    /// Reconciles tables, relationships and functions of the source with the database
+    /// in a single `bulk` request ordered as in `SyncPlan::changes`. With `cascade`,
+    /// the objects depending on the dangling ones are dropped along with them.
+    pub async fn sync(
+        &self,
+        kinds: &[TableKind],
+        filter: &ObjectFilter,
+        cascade: bool,
+    ) -> Result<Outcome, HasuraUtilsError> {
+        let source = &self.env.source;
+        let driver = self.env.driver;
+        let metadata = self.get_metadata().await?;
+        let (functions, function_names) = match driver.supports_functions() {
+            true => (
+                self.get_all_functions().await?,
+                self.get_all_function_names().await?,
+            ),
+            false => (vec![], vec![]),
+        };
+        let catalog = Catalog {
+            tables: self
+                .get_all_tables(TableKind::ALL, &ObjectFilter::default())
+                .await?,
+            relationships: self.get_all_fk_relationships().await?,
+            functions,
+            function_names,
+        };
+        let plan = SyncPlan::new(&metadata, source, catalog, kinds, filter);
+        let (summary, changes) = plan.changes(&metadata, source, driver, cascade);
+        if changes.is_empty() {
+            return Ok(Outcome::unchanged(
+                "metadata is already in sync with the database",
+            ));
+        }
+        self.send_metadata(summary, &BulkRequest::new(changes))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn table(schema: &str, name: &str) -> QualifiedTable {
+        QualifiedTable {
+            name: name.to_string(),
+            schema: schema.to_string(),
+        }
+    }
+
+    fn catalog() -> Catalog {
+        let tables = [("public", "users"), ("public", "orders")]
+            .iter()
+            .map(|(schema, name)| SQLTable {
+                table: table(schema, name),
+                kind: TableKind::Table,
+            })
+            .collect();
+        let relationships = serde_json::from_value(json!([{
+            "table_schema": "public",
+            "table_name": "orders",
+            "constraint_name": "orders_user_id_fkey",
+            "ref_table_schema": "public",
+            "ref_table_name": "users",
+            "column_mapping": { "user_id": "id" }
+        }]))
+        .unwrap();
+        Catalog {
+            tables,
+            relationships,
+            functions: vec![SQLFunction {
+                function: table("public", "search_orders"),
+                return_table: table("public", "orders"),
+                overloaded: false,
+            }],
+            function_names: vec![table("public", "search_orders")],
+        }
+    }
+
+    #[test]
+    fn plans_dangling_and_new_objects() {
+        let metadata: Metadata = serde_json::from_value(json!({
+            "version": 3,
+            "sources": [{
+                "name": "default",
+                "tables": [
+                    {
+                        "table": { "schema": "public", "name": "users" },
+                        "array_relationships": [{
+                            "name": "reviews",
+                            "using": { "foreign_key_constraint_on": {
+                                "column": "user_id",
+                                "table": { "schema": "public", "name": "reviews" }
+                            } }
+                        }]
+                    },
+                    {
+                        "table": { "schema": "public", "name": "dropped" },
+                        "object_relationships": [
+                            { "name": "user", "using": { "foreign_key_constraint_on": "user_id" } }
+                        ]
+                    }
+                ],
+                "functions": [{ "function": { "schema": "public", "name": "gone" } }]
+            }]
+        }))
+        .unwrap();
+        let plan = SyncPlan::new(
+            &metadata,
+            "default",
+            catalog(),
+            &[TableKind::Table],
+            &ObjectFilter::default(),
+        );
+        assert_eq!(
+            plan.drop_relationships,
+            [(table("public", "users"), "reviews".to_string())]
+        );
+        assert_eq!(plan.untrack_tables, [table("public", "dropped")]);
+        assert_eq!(plan.untrack_functions, [table("public", "gone")]);
+        assert_eq!(plan.track_tables.len(), 1);
+        assert_eq!(plan.track_tables[0].table, table("public", "orders"));
+        assert_eq!(plan.relationships.len(), 1);
+        assert_eq!(plan.track_functions, [table("public", "search_orders")]);
+
+        let (summary, changes) = plan.changes(&metadata, "default", Driver::Pg, false);
+        let types = changes
+            .iter()
+            .map(|change| serde_json::to_value(change).unwrap()["type"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                "pg_drop_relationship",
+                "pg_untrack_function",
+                "pg_untrack_table",
+                "pg_track_table",
+                "pg_create_array_relationship",
+                "pg_create_object_relationship",
+                "pg_track_function",
+            ]
+        );
+        assert_eq!(summary.len(), changes.len());
+    }
+
+    #[test]
+    fn skips_relationships_to_tables_left_untracked() {
+        let metadata: Metadata = serde_json::from_value(json!({
+            "version": 3,
+            "sources": [{ "name": "default", "tables": [] }]
+        }))
+        .unwrap();
+        let filter = ObjectFilter::new(None, &[], &["public.users".to_string()]);
+        let plan = SyncPlan::new(
+            &metadata,
+            "default",
+            catalog(),
+            &[TableKind::Table],
+            &filter,
+        );
+        assert_eq!(plan.track_tables.len(), 1);
+        assert!(plan.relationships.is_empty());
+    }
+
+    #[test]
+    fn skips_overloaded_functions() {
+        let metadata: Metadata = serde_json::from_value(json!({
+            "version": 3,
+            "sources": [{ "name": "default", "tables": [] }]
+        }))
+        .unwrap();
+        let mut catalog = catalog();
+        catalog.functions = ["int", "text"]
+            .iter()
+            .map(|_| SQLFunction {
+                function: table("public", "search_orders"),
+                return_table: table("public", "orders"),
+                overloaded: true,
+            })
+            .collect();
+        let plan = SyncPlan::new(
+            &metadata,
+            "default",
+            catalog,
+            &[TableKind::Table],
+            &ObjectFilter::default(),
+        );
+        assert!(plan.track_functions.is_empty());
+        assert_eq!(plan.skipped_functions, [table("public", "search_orders")]);
+    }
+}
@@ -1,12 +1,17 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
 
+use clap::ArgEnum;
 use console::style;
 use inflector::Inflector;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+use crate::driver::Driver;
 use crate::metadata::{
     ArrayRelUsing, ArrayRelUsingFKeyOn, ArrayRelationships, ObjRelUsing, ObjectRelationships,
-    QualifiedTable,
+    QualifiedFunction, QualifiedTable,
 };
 
 #[derive(Debug, Deserialize)]
@@ -17,7 +22,7 @@ pub enum RunSQLReponse {
 }
 
 impl RunSQLReponse {
-    pub fn into_inner<T>(&self) -> Result<T, serde_json::Error>
+    pub fn into_inner<T>(self) -> Result<T, serde_json::Error>
     where
         T: Default + DeserializeOwned,
     {
@@ -30,20 +35,376 @@ impl RunSQLReponse {
 
 pub trait MetadataRequest {}
 
+#[derive(Debug, Deserialize)]
+pub struct InconsistentMetadata {
+    pub is_consistent: bool,
+    pub inconsistent_objects: Vec<InconsistentObject>,
+}
+
+/// Metadata object that Hasura could not build, e.g. a table that was dropped
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InconsistentObject {
+    pub r#type: String,
+    /// e.g. `table default.public.orders`, missing on older versions of Hasura
+    #[serde(default)]
+    pub name: Option<String>,
+    pub reason: String,
+    pub definition: serde_json::Value,
+}
+
+impl InconsistentObject {
+    /// Source the object belongs to, if any. Remote schemas, actions etc. have none.
+    pub fn source(&self) -> Option<&str> {
+        match (self.r#type.as_str(), &self.definition) {
+            ("source", serde_json::Value::String(name)) => Some(name),
+            ("source", definition) => definition["name"].as_str(),
+            (_, definition) => definition["source"].as_str(),
+        }
+    }
+
+    pub fn is_remote_schema(&self) -> bool {
+        self.r#type == "remote_schema"
+    }
+
+    /// `name`, or the type and definition when Hasura does not report one
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.to_string(),
+            None => format!("{} {}", self.r#type, self.definition),
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
-pub struct TrackTable<'a> {
+pub struct NoArgs {}
+
+#[derive(Debug, Serialize)]
+pub struct DropInconsistentMetadata<'a> {
+    r#type: &'a str,
+    args: NoArgs,
+}
+
+impl<'a> Default for DropInconsistentMetadata<'a> {
+    fn default() -> Self {
+        Self {
+            r#type: "drop_inconsistent_metadata",
+            args: NoArgs {},
+        }
+    }
+}
+
+impl<'a> MetadataRequest for DropInconsistentMetadata<'a> {}
+
+#[derive(Debug, Serialize)]
+pub struct ReloadMetadata<'a> {
     r#type: &'a str,
+    args: ReloadMetadataArgs<'a>,
+}
+
+impl<'a> ReloadMetadata<'a> {
+    pub fn new(args: ReloadMetadataArgs<'a>) -> Self {
+        Self {
+            r#type: "reload_metadata",
+            args,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReloadMetadataArgs<'a> {
+    pub(crate) reload_sources: Vec<&'a str>,
+    pub(crate) reload_remote_schemas: bool,
+}
+
+impl<'a> ReloadMetadataArgs<'a> {
+    /// Reloads the sources of inconsistent objects, and the remote schemas when one
+    /// of them is inconsistent
+    pub fn for_inconsistent(objects: &'a [InconsistentObject]) -> Self {
+        let mut reload_sources = objects
+            .iter()
+            .filter_map(|o| o.source())
+            .collect::<Vec<_>>();
+        reload_sources.sort_unstable();
+        reload_sources.dedup();
+        Self {
+            reload_sources,
+            reload_remote_schemas: objects.iter().any(InconsistentObject::is_remote_schema),
+        }
+    }
+}
+
+impl<'a> MetadataRequest for ReloadMetadata<'a> {}
+
+#[derive(Debug, Serialize)]
+pub struct ReplaceMetadata<'a> {
+    r#type: &'a str,
+    /// Version 2 of `replace_metadata` responds with the inconsistent objects
+    version: u8,
+    args: ReplaceMetadataArgs<'a>,
+}
+
+impl<'a> ReplaceMetadata<'a> {
+    pub fn new(args: ReplaceMetadataArgs<'a>) -> Self {
+        Self {
+            r#type: "replace_metadata",
+            version: 2,
+            args,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplaceMetadataArgs<'a> {
+    pub(crate) allow_inconsistent_metadata: bool,
+    pub(crate) metadata: &'a serde_json::Value,
+}
+
+impl<'a> MetadataRequest for ReplaceMetadata<'a> {}
+
+#[derive(Debug, Serialize)]
+pub struct AddSource<'a> {
+    r#type: String,
+    args: AddSourceArgs<'a>,
+}
+
+impl<'a> AddSource<'a> {
+    pub fn new(driver: Driver, args: AddSourceArgs<'a>) -> Self {
+        Self {
+            r#type: driver.request_type("add_source"),
+            args,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AddSourceArgs<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) configuration: &'a serde_json::Value,
+}
+
+impl<'a> MetadataRequest for AddSource<'a> {}
+
+#[derive(Debug, Serialize)]
+pub struct UpdateSource<'a> {
+    r#type: String,
+    args: AddSourceArgs<'a>,
+}
+
+impl<'a> UpdateSource<'a> {
+    pub fn new(driver: Driver, args: AddSourceArgs<'a>) -> Self {
+        Self {
+            r#type: driver.request_type("update_source"),
+            args,
+        }
+    }
+}
+
+impl<'a> MetadataRequest for UpdateSource<'a> {}
+
+#[derive(Debug, Serialize)]
+pub struct DropSource<'a> {
+    r#type: String,
+    args: DropSourceArgs<'a>,
+}
+
+impl<'a> DropSource<'a> {
+    pub fn new(driver: Driver, args: DropSourceArgs<'a>) -> Self {
+        Self {
+            r#type: driver.request_type("drop_source"),
+            args,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DropSourceArgs<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) cascade: bool,
+}
+
+impl<'a> MetadataRequest for DropSource<'a> {}
+
+#[derive(Debug, Deserialize)]
+pub struct HGEVersion {
+    pub version: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrackTable<'a> {
+    r#type: String,
     args: TrackTableArgs<'a>,
 }
 impl<'a> TrackTable<'a> {
-    pub fn pg(args: TrackTableArgs<'a>) -> Self {
+    pub fn new(driver: Driver, args: TrackTableArgs<'a>) -> Self {
+        Self {
+            r#type: driver.request_type("track_table"),
+            args,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UntrackTable<'a> {
+    r#type: String,
+    args: UntrackTableArgs<'a>,
+}
+
+impl<'a> UntrackTable<'a> {
+    pub fn new(driver: Driver, args: UntrackTableArgs<'a>) -> Self {
+        Self {
+            r#type: driver.request_type("untrack_table"),
+            args,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UntrackTableArgs<'a> {
+    pub(crate) source: &'a str,
+    pub(crate) table: &'a QualifiedTable,
+    pub(crate) cascade: bool,
+}
+
+impl<'a> MetadataRequest for UntrackTable<'a> {}
+
+#[derive(Debug, Serialize)]
+pub struct TrackFunction<'a> {
+    r#type: String,
+    args: TrackFunctionArgs<'a>,
+}
+
+impl<'a> TrackFunction<'a> {
+    pub fn new(driver: Driver, args: TrackFunctionArgs<'a>) -> Self {
+        Self {
+            r#type: driver.request_type("track_function"),
+            args,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrackFunctionArgs<'a> {
+    pub(crate) source: &'a str,
+    pub(crate) function: &'a QualifiedFunction,
+}
+
+impl<'a> MetadataRequest for TrackFunction<'a> {}
+
+/// Kind of relation, as found by `sql::get_all_tables_sql`
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TableKind {
+    Table,
+    View,
+    #[clap(name = "materialized_view")]
+    MaterializedView,
+    Foreign,
+}
+
+impl TableKind {
+    pub const ALL: &'static [TableKind] = &[
+        TableKind::Table,
+        TableKind::View,
+        TableKind::MaterializedView,
+        TableKind::Foreign,
+    ];
+}
+
+impl Display for TableKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TableKind::Table => write!(f, "table"),
+            TableKind::View => write!(f, "view"),
+            TableKind::MaterializedView => write!(f, "materialized_view"),
+            TableKind::Foreign => write!(f, "foreign"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SQLTable {
+    #[serde(flatten)]
+    pub table: QualifiedTable,
+    pub kind: TableKind,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UntrackFunction<'a> {
+    r#type: String,
+    args: TrackFunctionArgs<'a>,
+}
+
+impl<'a> UntrackFunction<'a> {
+    pub fn new(driver: Driver, args: TrackFunctionArgs<'a>) -> Self {
+        Self {
+            r#type: driver.request_type("untrack_function"),
+            args,
+        }
+    }
+}
+
+impl<'a> MetadataRequest for UntrackFunction<'a> {}
+
+#[derive(Debug, Serialize)]
+pub struct DropRelationship<'a> {
+    r#type: String,
+    args: DropRelationshipArgs<'a>,
+}
+
+impl<'a> DropRelationship<'a> {
+    pub fn new(driver: Driver, args: DropRelationshipArgs<'a>) -> Self {
         Self {
-            r#type: "pg_track_table",
+            r#type: driver.request_type("drop_relationship"),
             args,
         }
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct DropRelationshipArgs<'a> {
+    pub(crate) source: &'a str,
+    pub(crate) table: &'a QualifiedTable,
+    pub(crate) relationship: &'a str,
+    pub(crate) cascade: bool,
+}
+
+impl<'a> MetadataRequest for DropRelationship<'a> {}
+
+/// Any request that can be part of a mixed `bulk` request
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum MetadataChange<'a> {
+    DropRelationship(DropRelationship<'a>),
+    UntrackTable(UntrackTable<'a>),
+    UntrackFunction(UntrackFunction<'a>),
+    TrackTable(TrackTable<'a>),
+    Relationship(Box<CreateRelationship<'a>>),
+    TrackFunction(TrackFunction<'a>),
+}
+
+impl<'a> MetadataRequest for MetadataChange<'a> {}
+
+/// A function returning `SETOF <table>`, as found by `sql::get_all_functions_sql`
+#[derive(Debug, Deserialize)]
+pub struct SQLFunction {
+    pub function: QualifiedFunction,
+    pub return_table: QualifiedTable,
+    /// Whether other functions have the same name, which Hasura cannot track
+    #[serde(default)]
+    pub overloaded: bool,
+}
+
+/// Overloaded functions of `functions`, once each and sorted
+pub fn overloaded_functions(functions: &[SQLFunction]) -> Vec<QualifiedFunction> {
+    functions
+        .iter()
+        .filter(|f| f.overloaded)
+        .map(|f| f.function.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
 pub enum RelType {
     Array,
     Object,
@@ -51,23 +412,23 @@ pub enum RelType {
 
 #[derive(Debug, Serialize)]
 pub struct CreateObjectRelationship<'a> {
-    r#type: &'static str,
+    r#type: String,
     args: CreateObjectRelationshipArgs<'a>,
 }
 
 impl<'a> CreateObjectRelationship<'a> {
-    fn pg(args: CreateObjectRelationshipArgs<'a>) -> Self {
+    fn new(driver: Driver, args: CreateObjectRelationshipArgs<'a>) -> Self {
         Self {
-            r#type: "pg_create_object_relationship",
+            r#type: driver.request_type("create_object_relationship"),
             args,
         }
     }
 }
 
 impl<'a> CreateArrayRelationship<'a> {
-    fn pg(args: CreateArrayRelationshipArgs<'a>) -> Self {
+    fn new(driver: Driver, args: CreateArrayRelationshipArgs<'a>) -> Self {
         Self {
-            r#type: "pg_create_array_relationship",
+            r#type: driver.request_type("create_array_relationship"),
             args,
         }
     }
@@ -75,7 +436,7 @@ impl<'a> CreateArrayRelationship<'a> {
 
 #[derive(Debug, Serialize)]
 pub struct CreateArrayRelationship<'a> {
-    r#type: &'static str,
+    r#type: String,
     args: CreateArrayRelationshipArgs<'a>,
 }
 
@@ -94,22 +455,22 @@ pub struct SQLFKRelationship {
     pub constraint_name: String,
     pub ref_table_schema: String,
     pub ref_table_name: String,
-    pub column_mapping: HashMap<String, String>,
+    pub column_mapping: BTreeMap<String, String>,
 }
 
 impl Display for SQLFKRelationship {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let key = self.column_mapping.keys().nth(0).unwrap();
-        let value = self.column_mapping.get(key).unwrap();
+        let key = format_columns(self.column_mapping.keys());
+        let value = format_columns(self.column_mapping.values());
         let obj = format!(
             "{} {} {}  -  {}.{} --> {}.{}",
             style(&self.table_name).bold(),
             style("-->").bold(),
             style(&self.ref_table_name).bold(),
             self.table_name,
-            style(key).magenta(),
+            style(&key).magenta(),
             self.ref_table_name,
-            style(value).magenta()
+            style(&value).magenta()
         );
         let arr = format!(
             "{} {} {} {} {}  -  {}.{} --> {}.{}",
@@ -119,26 +480,80 @@ impl Display for SQLFKRelationship {
             style(&self.table_name).bold(),
             style("]").bold(),
             self.table_name,
-            style(key).magenta(),
+            style(&key).magenta(),
             self.ref_table_name,
-            style(value).magenta(),
+            style(&value).magenta(),
         );
         write!(f, "{}\n\n{}", &obj, &arr)
     }
 }
 
+/// `col` for a single column, `(col_a, col_b)` for composite keys
+fn format_columns<'a>(columns: impl ExactSizeIterator<Item = &'a String>) -> String {
+    let single = columns.len() == 1;
+    let joined = columns.map(|c| &c[..]).collect::<Vec<_>>().join(", ");
+    if single {
+        joined
+    } else {
+        format!("({joined})")
+    }
+}
+
 impl SQLFKRelationship {
+    /// `<schema>.<table>.<columns> --> <schema>.<table>.<columns>`, unstyled
+    pub fn describe(&self) -> String {
+        format!(
+            "{}.{} --> {}.{}",
+            self.table(),
+            format_columns(self.column_mapping.keys()),
+            self.ref_table(),
+            format_columns(self.column_mapping.values())
+        )
+    }
+
+    pub fn table(&self) -> QualifiedTable {
+        QualifiedTable {
+            name: self.table_name.to_string(),
+            schema: self.table_schema.to_string(),
+        }
+    }
+
+    pub fn ref_table(&self) -> QualifiedTable {
+        QualifiedTable {
+            name: self.ref_table_name.to_string(),
+            schema: self.ref_table_schema.to_string(),
+        }
+    }
+
+    pub fn is_self_referencing(&self) -> bool {
+        self.table() == self.ref_table()
+    }
+
+    /// Whether `table` is the referencing or the referenced side of this foreign key
+    pub fn involves(&self, table: &QualifiedTable) -> bool {
+        &self.table() == table || &self.ref_table() == table
+    }
+
+    /// Referencing columns of the foreign key, sorted by name
+    pub fn columns(&self) -> Vec<String> {
+        self.column_mapping.keys().cloned().collect()
+    }
+
     pub fn get_relationships<'a>(
         &'a self,
         source: &'a str,
+        driver: Driver,
     ) -> (CreateObjectRelationship<'a>, CreateArrayRelationship<'a>) {
-        let key = self.column_mapping.keys().nth(0).unwrap();
         let obj_args = CreateObjectRelationshipArgs {
             rel: ObjectRelationships {
                 name: Inflector::to_singular(&self.ref_table_name),
                 using: ObjRelUsing {
-                    foreign_key_constraint_on: key.to_string(),
+                    foreign_key_constraint_on: Some(self.columns().into()),
+                    manual_configuration: None,
+                    extra: serde_json::Map::new(),
                 },
+                comment: None,
+                extra: serde_json::Map::new(),
             },
             source,
             table: QualifiedTable {
@@ -155,19 +570,23 @@ impl SQLFKRelationship {
             rel: ArrayRelationships {
                 name: Inflector::to_plural(&self.table_name),
                 using: ArrayRelUsing {
-                    foreign_key_constraint_on: ArrayRelUsingFKeyOn {
-                        column: key.to_string(),
+                    foreign_key_constraint_on: Some(ArrayRelUsingFKeyOn {
+                        columns: self.columns().into(),
                         table: QualifiedTable {
                             name: self.table_name.to_string(),
                             schema: self.table_schema.to_string(),
                         },
-                    },
+                    }),
+                    manual_configuration: None,
+                    extra: serde_json::Map::new(),
                 },
+                comment: None,
+                extra: serde_json::Map::new(),
             },
         };
         (
-            CreateObjectRelationship::pg(obj_args),
-            CreateArrayRelationship::pg(arr_args),
+            CreateObjectRelationship::new(driver, obj_args),
+            CreateArrayRelationship::new(driver, arr_args),
         )
     }
 }
@@ -189,6 +608,45 @@ pub enum CreateRelationship<'a> {
 
 impl<'a> MetadataRequest for CreateRelationship<'a> {}
 
+impl<'a> CreateRelationship<'a> {
+    /// Table the relationship is created on
+    pub fn table(&self) -> &QualifiedTable {
+        match self {
+            CreateRelationship::Object(rel) => &rel.args.table,
+            CreateRelationship::Array(rel) => &rel.args.table,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            CreateRelationship::Object(rel) => &rel.args.rel.name,
+            CreateRelationship::Array(rel) => &rel.args.rel.name,
+        }
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        match self {
+            CreateRelationship::Object(rel) => rel.args.rel.name = name,
+            CreateRelationship::Array(rel) => rel.args.rel.name = name,
+        }
+    }
+
+    /// Dry-run line naming the relationship on its table, with the foreign key `fk`
+    /// it is created from as detail
+    pub fn summary(&self, fk: &SQLFKRelationship) -> String {
+        let kind = match self {
+            CreateRelationship::Object(_) => "object",
+            CreateRelationship::Array(_) => "array",
+        };
+        format!(
+            "create {kind} relationship {} on {}  -  {}",
+            self.name(),
+            self.table(),
+            fk.describe()
+        )
+    }
+}
+
 impl<'a> From<CreateObjectRelationship<'a>> for CreateRelationship<'a> {
     fn from(rel: CreateObjectRelationship<'a>) -> Self {
         CreateRelationship::Object(rel)
@@ -223,3 +681,57 @@ impl<T: MetadataRequest> BulkRequest<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn reloads_remote_schemas_only_when_one_is_inconsistent() {
+        let objects: Vec<InconsistentObject> = serde_json::from_value(json!([
+            { "type": "table", "reason": "no such table", "definition": { "source": "b" } },
+            { "type": "action", "reason": "bad handler", "definition": { "name": "login" } },
+            { "type": "source", "reason": "cannot connect", "definition": "a" },
+            { "type": "function", "reason": "no such function", "definition": { "source": "b" } },
+        ]))
+        .unwrap();
+        let args = ReloadMetadataArgs::for_inconsistent(&objects);
+        assert_eq!(args.reload_sources, ["a", "b"]);
+        assert!(!args.reload_remote_schemas);
+
+        let objects: Vec<InconsistentObject> = serde_json::from_value(json!([
+            { "type": "remote_schema", "reason": "unreachable", "definition": { "name": "countries" } },
+        ]))
+        .unwrap();
+        let args = ReloadMetadataArgs::for_inconsistent(&objects);
+        assert!(args.reload_sources.is_empty());
+        assert!(args.reload_remote_schemas);
+    }
+
+    #[test]
+    fn composite_foreign_key_uses_every_column() {
+        let fk: SQLFKRelationship = serde_json::from_value(json!({
+            "table_schema": "public",
+            "table_name": "orders",
+            "constraint_name": "orders_tenant_id_user_id_fkey",
+            "ref_table_schema": "public",
+            "ref_table_name": "users",
+            "column_mapping": { "user_id": "id", "tenant_id": "tenant_id" }
+        }))
+        .unwrap();
+        let (obj_rel, arr_rel) = fk.get_relationships("default", Driver::Pg);
+        assert_eq!(
+            serde_json::to_value(&obj_rel).unwrap()["args"]["using"],
+            json!({ "foreign_key_constraint_on": ["tenant_id", "user_id"] })
+        );
+        assert_eq!(
+            serde_json::to_value(&arr_rel).unwrap()["args"]["using"],
+            json!({ "foreign_key_constraint_on": {
+                "columns": ["tenant_id", "user_id"],
+                "table": { "schema": "public", "name": "orders" }
+            } })
+        );
+    }
+}
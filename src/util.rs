@@ -1,14 +1,64 @@
 use reqwest::{Client, Response};
+use serde::Serialize;
 
-use crate::error::{HasuraUtilsError, OtherError};
-use crate::metadata::QualifiedTable;
+use crate::driver::Driver;
+use crate::error::{HasuraApiError, HasuraUtilsError, OtherError};
+use crate::filter::ObjectFilter;
+use crate::metadata::{QualifiedFunction, QualifiedTable};
 use crate::sql;
-use crate::types::{BulkRequest, RunSQLReponse, SQLFKRelationship, TrackTable, TrackTableArgs};
+use crate::types::{
+    overloaded_functions, BulkRequest, DropInconsistentMetadata, DropRelationship,
+    DropRelationshipArgs, HGEVersion, InconsistentMetadata, InconsistentObject, ReloadMetadata,
+    ReloadMetadataArgs, ReplaceMetadata, ReplaceMetadataArgs, RunSQLReponse, SQLFKRelationship,
+    SQLFunction, SQLTable, TableKind, TrackFunction, TrackFunctionArgs, TrackTable, TrackTableArgs,
+    UntrackTable, UntrackTableArgs,
+};
 use crate::{env::EnvVars, metadata::Metadata};
 
+pub(crate) trait ApiResponse {
+    /// Like `Response::error_for_status`, but keeps the Hasura error body
+    async fn error_for_api_status(self) -> Result<Response, HasuraUtilsError>;
+}
+
+impl ApiResponse for Response {
+    async fn error_for_api_status(self) -> Result<Response, HasuraUtilsError> {
+        let status = self.status();
+        if status.is_success() {
+            return Ok(self);
+        }
+        let endpoint = self.url().path().to_string();
+        let body = self.text().await?;
+        let mut err = serde_json::from_str::<HasuraApiError>(&body).unwrap_or(HasuraApiError {
+            code: status.as_str().to_string(),
+            error: body,
+            path: "$".to_string(),
+            internal: None,
+            endpoint: String::new(),
+            request: None,
+        });
+        err.endpoint = endpoint;
+        Err(HasuraUtilsError::HasuraApi(Box::new(err)))
+    }
+}
+
+#[derive(Clone)]
 pub struct HasuraUtils {
     pub client: Client,
     pub env: EnvVars,
+    /// Print the planned metadata requests instead of sending them
+    pub dry_run: bool,
+}
+
+/// Result of a command changing the metadata
+#[derive(Debug)]
+pub enum Outcome {
+    /// Response body of the metadata API
+    Applied(serde_json::Value),
+    /// Requests that would have been sent, in dry-run mode
+    Planned {
+        summary: Vec<String>,
+        request: serde_json::Value,
+    },
 }
 
 #[derive(Debug)]
@@ -18,6 +68,36 @@ pub enum HGEHealth {
     Error,
 }
 
+/// Sources selected on the command line, or with `--all-sources` every source of
+/// `metadata`, with the driver of their kind. `--driver` is used for sources missing
+/// from the metadata, and sources of a kind no driver supports are an error.
+fn select_sources(
+    env: &EnvVars,
+    metadata: &Metadata,
+) -> Vec<(String, Result<Driver, HasuraUtilsError>)> {
+    let names = match env.all_sources {
+        true => metadata.sources().iter().map(|s| s.name.clone()).collect(),
+        false => env.sources.clone(),
+    };
+    names
+        .into_iter()
+        .map(|name| {
+            let driver = match metadata.sources().iter().find(|s| s.name == name) {
+                Some(source) => Driver::require_kind(source.kind()),
+                None => Ok(env.driver),
+            };
+            (name, driver)
+        })
+        .collect()
+}
+
+impl Outcome {
+    /// Outcome of a command with nothing to change, which is not an error
+    pub fn unchanged(message: &str) -> Self {
+        Outcome::Applied(serde_json::json!({ "message": message }))
+    }
+}
+
 impl HasuraUtils {
     pub async fn check_health(&self) -> Result<HGEHealth, HasuraUtilsError> {
         let res = &self
@@ -35,139 +115,600 @@ impl HasuraUtils {
         Ok(state)
     }
 
+    /// A copy running commands against `source` with `driver`
+    pub fn with_source(&self, source: &str, driver: Driver) -> Self {
+        let mut utils = self.clone();
+        utils.env.source = source.to_string();
+        utils.env.driver = driver;
+        utils
+    }
+
+    /// Sources selected on the command line, or every source of the metadata
+    /// with `--all-sources`, each with the driver of its kind in the metadata.
+    /// Fails when neither selects a source.
+    pub async fn target_sources(
+        &self,
+    ) -> Result<Vec<(String, Result<Driver, HasuraUtilsError>)>, HasuraUtilsError> {
+        self.env.require_sources()?;
+        let metadata = self.get_metadata().await?;
+        Ok(select_sources(&self.env, &metadata))
+    }
+
     pub async fn get_metadata(&self) -> Result<Metadata, HasuraUtilsError> {
+        Ok(serde_json::from_value(self.export_metadata().await?)?)
+    }
+
+    /// The complete `export_metadata` document
+    pub async fn export_metadata(&self) -> Result<serde_json::Value, HasuraUtilsError> {
         let res = self
             .client
             .post(&self.env.metadata_url)
             .body(r#"{"type": "export_metadata", "args": {}}"#)
             .send()
             .await?
-            .json::<Metadata>()
+            .error_for_api_status()
+            .await?
+            .json::<serde_json::Value>()
             .await?;
         Ok(res)
     }
 
-    pub async fn get_all_tables(&self) -> Result<Vec<QualifiedTable>, HasuraUtilsError> {
-        let body = &self.env.get_run_sql(sql::get_all_tables_sql());
+    /// Replaces the whole metadata with `metadata`. Unless `allow_inconsistent_metadata`
+    /// is set, Hasura rejects metadata with inconsistent objects.
+    pub async fn replace_metadata(
+        &self,
+        metadata: &serde_json::Value,
+        allow_inconsistent_metadata: bool,
+    ) -> Result<Outcome, HasuraUtilsError> {
+        let sources = metadata["sources"].as_array().cloned().unwrap_or_default();
+        let summary = sources
+            .iter()
+            .map(|source| {
+                let count = |key: &str| source[key].as_array().map(Vec::len).unwrap_or_default();
+                format!(
+                    "replace source {} with {} table(s) and {} function(s)",
+                    source["name"].as_str().unwrap_or_default(),
+                    count("tables"),
+                    count("functions")
+                )
+            })
+            .collect();
+        let body = ReplaceMetadata::new(ReplaceMetadataArgs {
+            allow_inconsistent_metadata,
+            metadata,
+        });
+        self.send_metadata(summary, &body).await
+    }
+
+    pub async fn get_version(&self) -> Result<HGEVersion, HasuraUtilsError> {
+        let res = self
+            .client
+            .get(&self.env.version_url)
+            .send()
+            .await?
+            .error_for_api_status()
+            .await?
+            .json::<HGEVersion>()
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn get_inconsistent_metadata(
+        &self,
+    ) -> Result<InconsistentMetadata, HasuraUtilsError> {
+        let res = self
+            .client
+            .post(&self.env.metadata_url)
+            .body(r#"{"type": "get_inconsistent_metadata", "args": {}}"#)
+            .send()
+            .await?
+            .error_for_api_status()
+            .await?
+            .json::<InconsistentMetadata>()
+            .await?;
+        Ok(res)
+    }
+
+    /// Inconsistent objects sorted by source, type and name
+    pub async fn get_inconsistent_objects(
+        &self,
+    ) -> Result<Vec<InconsistentObject>, HasuraUtilsError> {
+        let mut objects = self.get_inconsistent_metadata().await?.inconsistent_objects;
+        objects.sort_by_cached_key(|o| {
+            (
+                o.source().map(str::to_string),
+                o.r#type.clone(),
+                o.display_name(),
+            )
+        });
+        Ok(objects)
+    }
+
+    /// Removes every inconsistent object from the metadata
+    pub async fn drop_inconsistent_metadata(&self) -> Result<Outcome, HasuraUtilsError> {
+        let objects = self.get_inconsistent_objects().await?;
+        if objects.is_empty() {
+            return Ok(Outcome::unchanged("metadata is already consistent"));
+        }
+        let summary = objects
+            .iter()
+            .map(|o| format!("drop inconsistent {}", o.display_name()))
+            .collect();
+        self.send_metadata(summary, &DropInconsistentMetadata::default())
+            .await
+    }
+
+    /// Reloads the sources with inconsistent objects, and the remote schemas when
+    /// a remote schema is inconsistent, e.g. after a database came back up
+    pub async fn reload_inconsistent_metadata(&self) -> Result<Outcome, HasuraUtilsError> {
+        let objects = self.get_inconsistent_objects().await?;
+        if objects.is_empty() {
+            return Ok(Outcome::unchanged("metadata is already consistent"));
+        }
+        let args = ReloadMetadataArgs::for_inconsistent(&objects);
+        let mut summary = args
+            .reload_sources
+            .iter()
+            .map(|source| format!("reload source {source}"))
+            .collect::<Vec<_>>();
+        if args.reload_remote_schemas {
+            summary.push("reload remote schemas".to_string());
+        }
+        let body = ReloadMetadata::new(args);
+        self.send_metadata(summary, &body).await
+    }
+
+    pub async fn ping_source(&self, source: &str, driver: Driver) -> Result<(), HasuraUtilsError> {
+        let body = &self.env.get_source_run_sql(driver, source, "SELECT 1");
+        self.client
+            .post(&self.env.query_url)
+            .json(body)
+            .send()
+            .await?
+            .error_for_api_status()
+            .await?
+            .json::<RunSQLReponse>()
+            .await?;
+        Ok(())
+    }
+
+    /// Tables, views, materialized views and foreign tables of the given `kinds`
+    /// selected by `filter`
+    pub async fn get_all_tables(
+        &self,
+        kinds: &[TableKind],
+        filter: &ObjectFilter,
+    ) -> Result<Vec<SQLTable>, HasuraUtilsError> {
+        let body = &self
+            .env
+            .get_run_sql(sql::get_all_tables_sql(self.env.driver));
         let resp = self
             .client
             .post(&self.env.query_url)
             .json(body)
             .send()
             .await?
-            .error_for_status()?
+            .error_for_api_status()
+            .await?
             .json::<RunSQLReponse>()
             .await?
-            .into_inner::<Vec<QualifiedTable>>()?;
+            .into_inner::<Vec<SQLTable>>()?
+            .into_iter()
+            .filter(|t| kinds.contains(&t.kind) && filter.matches(&t.table))
+            .collect();
         Ok(resp)
     }
 
     pub async fn track_all_tables(
         &self,
-        exclude: &Option<Vec<String>>,
-    ) -> Result<(), HasuraUtilsError> {
+        ignore: &[String],
+        kinds: &[TableKind],
+        filter: &ObjectFilter,
+    ) -> Result<Outcome, HasuraUtilsError> {
         let metadata = self.get_metadata().await?;
-        let all_tables = self.get_all_tables().await?;
-        let untracked_tables = metadata.get_untracked_tables(all_tables);
-        let filtered_tables = untracked_tables
-            .into_iter()
-            .filter(|t| {
-                exclude
-                    .as_ref()
-                    .map(|ex| !ex.contains(&t.name))
-                    .unwrap_or(false)
-            })
-            .collect::<Vec<_>>();
-        if filtered_tables.len() == 0 {
+        let all_tables = self.get_all_tables(kinds, filter).await?;
+        let filtered_tables =
+            metadata.select_untracked_tables(all_tables, ignore, &self.env.source);
+        if filtered_tables.is_empty() {
             return Err(OtherError("Database has no untracked tables").into());
         }
         let args: Vec<TrackTable> = filtered_tables
             .iter()
-            .map(|table| TrackTableArgs {
-                table,
+            .map(|t| TrackTableArgs {
+                table: &t.table,
                 source: &self.env.source,
             })
-            .map(|args| TrackTable::pg(args))
+            .map(|args| TrackTable::new(self.env.driver, args))
             .collect();
-        let res = self
-            .client
-            .post(&self.env.metadata_url)
-            .json(&BulkRequest::new(args))
-            .send()
-            .await?
-            .error_for_status();
-        println!("{res:?}");
-        Ok(())
+        let summary = filtered_tables
+            .iter()
+            .map(|t| format!("track {} {}", t.kind, t.table))
+            .collect();
+        self.send_metadata(summary, &BulkRequest::new(args)).await
     }
 
-    pub async fn track_table(&self, table: QualifiedTable) -> Result<Response, HasuraUtilsError> {
+    pub async fn track_table(&self, table: QualifiedTable) -> Result<Outcome, HasuraUtilsError> {
         let metadata = self.get_metadata().await?;
-        let untracked_tables = metadata.get_untracked_tables(vec![table]);
+        if metadata.is_table_tracked(&table, &self.env.source) {
+            return Err(HasuraUtilsError::AlreadyTracked(format!("table {table}")));
+        }
         let args = TrackTableArgs {
-            table: untracked_tables.first().expect("table is already tracked!"),
+            table: &table,
             source: &self.env.source,
         };
-        let body = TrackTable::pg(args);
-        let res = self
-            .client
-            .post(&self.env.metadata_url)
-            .json(&body)
-            .send()
-            .await?
-            .error_for_status()?;
-        Ok(res)
+        let summary = vec![format!("track table {}", args.table)];
+        self.send_metadata(summary, &TrackTable::new(self.env.driver, args))
+            .await
+    }
+
+    pub async fn untrack_table(
+        &self,
+        table: QualifiedTable,
+        cascade: bool,
+    ) -> Result<Outcome, HasuraUtilsError> {
+        let metadata = self.get_metadata().await?;
+        let tables = metadata
+            .get_tracked_tables(&self.env.source)
+            .into_iter()
+            .filter(|&t| t == &table)
+            .collect::<Vec<_>>();
+        if tables.is_empty() {
+            return Err(OtherError("table is not tracked").into());
+        }
+        self.send_untrack_tables(&tables, cascade).await
+    }
+
+    pub async fn untrack_schema_tables(
+        &self,
+        schema: &str,
+        cascade: bool,
+    ) -> Result<Outcome, HasuraUtilsError> {
+        let metadata = self.get_metadata().await?;
+        let tables = metadata
+            .get_tracked_tables(&self.env.source)
+            .into_iter()
+            .filter(|t| t.schema == schema)
+            .collect::<Vec<_>>();
+        if tables.is_empty() {
+            return Err(OtherError("Schema has no tracked tables").into());
+        }
+        self.send_untrack_tables(&tables, cascade).await
+    }
+
+    async fn send_untrack_tables(
+        &self,
+        tables: &[&QualifiedTable],
+        cascade: bool,
+    ) -> Result<Outcome, HasuraUtilsError> {
+        let args: Vec<UntrackTable> = tables
+            .iter()
+            .map(|table| UntrackTableArgs {
+                table,
+                source: &self.env.source,
+                cascade,
+            })
+            .map(|args| UntrackTable::new(self.env.driver, args))
+            .collect();
+        let summary = tables
+            .iter()
+            .map(|t| match cascade {
+                true => format!("untrack table {t} (cascade)"),
+                false => format!("untrack table {t}"),
+            })
+            .collect();
+        self.send_metadata(summary, &BulkRequest::new(args)).await
     }
 
     pub async fn get_all_fk_relationships(
         &self,
     ) -> Result<Vec<SQLFKRelationship>, HasuraUtilsError> {
-        let body = &self.env.get_run_sql(sql::get_all_fk_relationships());
+        let body = &self
+            .env
+            .get_run_sql(sql::get_all_fk_relationships(self.env.driver));
         let resp = self
             .client
             .post(&self.env.query_url)
             .json(body)
             .send()
             .await?
-            .error_for_status()?
+            .error_for_api_status()
+            .await?
             .json::<RunSQLReponse>()
             .await?
             .into_inner::<Vec<SQLFKRelationship>>()?;
         Ok(resp)
     }
 
-    pub async fn track_all_relationships(&self) -> Result<Response, HasuraUtilsError> {
+    /// Tracks the relationships of every foreign key whose both sides are selected by `filter`
+    pub async fn track_all_relationships(
+        &self,
+        filter: &ObjectFilter,
+    ) -> Result<Outcome, HasuraUtilsError> {
         let metadata = self.get_metadata().await?;
-        let relationships = self.get_all_fk_relationships().await?;
-        let untracked_relationships =
-            metadata.get_untracked_relationships(&relationships, &self.env.source);
-        if untracked_relationships.len() == 0 {
+        let relationships = self
+            .get_all_fk_relationships()
+            .await?
+            .into_iter()
+            .filter(|rel| filter.matches(&rel.table()) && filter.matches(&rel.ref_table()))
+            .collect::<Vec<_>>();
+        let planned =
+            metadata.get_untracked_relationships(&relationships, &self.env.source, self.env.driver);
+        if planned.is_empty() {
             return Err(OtherError("Database has no untracked relationships").into());
         }
-        let res = self
+        let summary = planned.iter().map(|(fk, rel)| rel.summary(fk)).collect();
+        let requests = planned.into_iter().map(|(_, rel)| rel).collect();
+        self.send_metadata(summary, &BulkRequest::new(requests))
+            .await
+    }
+
+    /// Drops the relationships using `foreign_key_constraint_on` whose foreign key
+    /// no longer exists in the database
+    pub async fn prune_relationships(&self, cascade: bool) -> Result<Outcome, HasuraUtilsError> {
+        let metadata = self.get_metadata().await?;
+        let relationships = self.get_all_fk_relationships().await?;
+        let dangling = metadata.get_dangling_relationships(&relationships, &self.env.source);
+        if dangling.is_empty() {
+            return Ok(Outcome::unchanged("metadata has no dangling relationships"));
+        }
+        let summary = dangling
+            .iter()
+            .map(|(table, name)| format!("drop relationship {name} on {table}"))
+            .collect();
+        let args: Vec<DropRelationship> = dangling
+            .into_iter()
+            .map(|(table, relationship)| {
+                DropRelationship::new(
+                    self.env.driver,
+                    DropRelationshipArgs {
+                        source: &self.env.source,
+                        table,
+                        relationship,
+                        cascade,
+                    },
+                )
+            })
+            .collect();
+        self.send_metadata(summary, &BulkRequest::new(args)).await
+    }
+
+    /// Tracks the relationships of every foreign key where `table` is either
+    /// the referencing or the referenced side
+    pub async fn track_relationships(
+        &self,
+        table: QualifiedTable,
+    ) -> Result<Outcome, HasuraUtilsError> {
+        let metadata = self.get_metadata().await?;
+        let relationships = self
+            .get_all_fk_relationships()
+            .await?
+            .into_iter()
+            .filter(|rel| rel.involves(&table))
+            .collect::<Vec<_>>();
+        let planned =
+            metadata.get_untracked_relationships(&relationships, &self.env.source, self.env.driver);
+        if planned.is_empty() {
+            return Err(OtherError("Table has no untracked relationships").into());
+        }
+        let summary = planned.iter().map(|(fk, rel)| rel.summary(fk)).collect();
+        let requests = planned.into_iter().map(|(_, rel)| rel).collect();
+        self.send_metadata(summary, &BulkRequest::new(requests))
+            .await
+    }
+
+    pub async fn get_all_functions(&self) -> Result<Vec<SQLFunction>, HasuraUtilsError> {
+        if !self.env.driver.supports_functions() {
+            return Err(OtherError("Driver does not support functions").into());
+        }
+        let body = &self.env.get_run_sql(sql::get_all_functions_sql());
+        let resp = self
             .client
-            .post(&self.env.metadata_url)
-            .json(&BulkRequest::new(untracked_relationships))
+            .post(&self.env.query_url)
+            .json(body)
             .send()
             .await?
-            .error_for_status()?;
-        Ok(res)
+            .error_for_api_status()
+            .await?
+            .json::<RunSQLReponse>()
+            .await?
+            .into_inner::<Vec<SQLFunction>>()?;
+        Ok(resp)
     }
 
-    pub async fn track_relationships(&self) -> Result<Response, HasuraUtilsError> {
+    /// Every user defined function, trackable or not
+    pub async fn get_all_function_names(&self) -> Result<Vec<QualifiedFunction>, HasuraUtilsError> {
+        if !self.env.driver.supports_functions() {
+            return Err(OtherError("Driver does not support functions").into());
+        }
+        let body = &self.env.get_run_sql(sql::get_all_function_names_sql());
+        let resp = self
+            .client
+            .post(&self.env.query_url)
+            .json(body)
+            .send()
+            .await?
+            .error_for_api_status()
+            .await?
+            .json::<RunSQLReponse>()
+            .await?
+            .into_inner::<Vec<QualifiedFunction>>()?;
+        Ok(resp)
+    }
+
+    pub async fn track_all_functions(
+        &self,
+        filter: &ObjectFilter,
+    ) -> Result<Outcome, HasuraUtilsError> {
         let metadata = self.get_metadata().await?;
-        let relationships = self.get_all_fk_relationships().await?;
-        let untracked_relationships =
-            metadata.get_untracked_relationships(&relationships, &self.env.source);
-        if untracked_relationships.len() == 0 {
-            return Err(OtherError("Database has no untracked relationships").into());
+        let functions = self
+            .get_all_functions()
+            .await?
+            .into_iter()
+            .filter(|f| filter.matches(&f.function))
+            .collect::<Vec<_>>();
+        let overloaded = overloaded_functions(&functions);
+        let untracked_functions = metadata.get_untracked_functions(functions, &self.env.source);
+        if untracked_functions.is_empty() {
+            return Err(OtherError("Database has no untracked functions").into());
+        }
+        self.send_track_functions(&untracked_functions, &overloaded)
+            .await
+    }
+
+    pub async fn track_function(
+        &self,
+        function: QualifiedFunction,
+    ) -> Result<Outcome, HasuraUtilsError> {
+        let metadata = self.get_metadata().await?;
+        let functions = self
+            .get_all_functions()
+            .await?
+            .into_iter()
+            .filter(|f| f.function == function)
+            .collect::<Vec<_>>();
+        if functions.iter().any(|f| f.overloaded) {
+            return Err(OtherError("function is overloaded, which Hasura cannot track").into());
+        }
+        let untracked_functions = metadata.get_untracked_functions(functions, &self.env.source);
+        if untracked_functions.is_empty() {
+            return Err(OtherError(
+                "function is already tracked or does not return SETOF a tracked table",
+            )
+            .into());
+        }
+        self.send_track_functions(&untracked_functions, &[]).await
+    }
+
+    /// Tracks `functions`, listing the `overloaded` ones as skipped in the summary
+    async fn send_track_functions(
+        &self,
+        functions: &[QualifiedFunction],
+        overloaded: &[QualifiedFunction],
+    ) -> Result<Outcome, HasuraUtilsError> {
+        let args: Vec<TrackFunction> = functions
+            .iter()
+            .map(|function| TrackFunctionArgs {
+                function,
+                source: &self.env.source,
+            })
+            .map(|args| TrackFunction::new(self.env.driver, args))
+            .collect();
+        let summary = functions
+            .iter()
+            .map(|f| format!("track function {f}"))
+            .chain(
+                overloaded
+                    .iter()
+                    .map(|f| format!("skip overloaded function {f}")),
+            )
+            .collect();
+        self.send_metadata(summary, &BulkRequest::new(args)).await
+    }
+
+    /// Sends a metadata API request, or in dry-run mode returns the summary and
+    /// the request body that would have been sent
+    pub(crate) async fn send_metadata<T: Serialize>(
+        &self,
+        summary: Vec<String>,
+        body: &T,
+    ) -> Result<Outcome, HasuraUtilsError> {
+        if self.dry_run {
+            return Ok(Outcome::Planned {
+                summary,
+                request: serde_json::to_value(body)?,
+            });
         }
         let res = self
             .client
             .post(&self.env.metadata_url)
-            .json(&BulkRequest::new(untracked_relationships))
+            .json(body)
             .send()
             .await?
-            .error_for_status()?;
-        Ok(res)
+            .error_for_api_status()
+            .await;
+        match res {
+            Ok(res) => Ok(Outcome::Applied(res.json::<serde_json::Value>().await?)),
+            Err(HasuraUtilsError::HasuraApi(mut err)) => {
+                let request = serde_json::to_value(body)?;
+                if request["type"] == "bulk" {
+                    err.request = err.bulk_index().map(|i| request["args"][i].clone());
+                }
+                Err(HasuraUtilsError::HasuraApi(err))
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn metadata(sources: &[(&str, &str)]) -> Metadata {
+        let sources = sources
+            .iter()
+            .map(|(name, kind)| json!({ "name": name, "kind": kind, "tables": [] }))
+            .collect::<Vec<_>>();
+        serde_json::from_value(json!({ "version": 3, "sources": sources })).unwrap()
+    }
+
+    fn drivers(env: &EnvVars, metadata: &Metadata) -> Vec<(String, Result<Driver, String>)> {
+        select_sources(env, metadata)
+            .into_iter()
+            .map(|(name, driver)| (name, driver.map_err(|err| err.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn selects_the_sources_of_the_command_line() {
+        let metadata = metadata(&[("default", "postgres"), ("reporting", "postgres")]);
+        let env = EnvVars::for_test(&["reporting"], false);
+        assert_eq!(
+            drivers(&env, &metadata),
+            [("reporting".to_string(), Ok(Driver::Pg))]
+        );
+    }
+
+    #[test]
+    fn selects_every_source_of_the_metadata_with_all_sources() {
+        let env = EnvVars::for_test(&[], true);
+        let metadata = metadata(&[("default", "postgres"), ("reporting", "postgres")]);
+        let names = select_sources(&env, &metadata)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["default", "reporting"]);
+        assert!(env.selects_many_sources());
+    }
+
+    #[test]
+    fn resolves_the_driver_of_each_source_from_its_kind() {
+        let metadata = metadata(&[
+            ("default", "postgres"),
+            ("warehouse", "mssql"),
+            ("events", "cockroach"),
+            ("search", "bigquery"),
+        ]);
+        let mut all_sources = EnvVars::for_test(&[], true);
+        all_sources.driver = Driver::Citus;
+        assert_eq!(
+            drivers(&all_sources, &metadata),
+            [
+                ("default".to_string(), Ok(Driver::Pg)),
+                ("warehouse".to_string(), Ok(Driver::Mssql)),
+                ("events".to_string(), Ok(Driver::Cockroach)),
+                (
+                    "search".to_string(),
+                    Err("unsupported kind bigquery".to_string())
+                ),
+            ]
+        );
+        let mut missing = EnvVars::for_test(&["missing"], false);
+        missing.driver = Driver::Mssql;
+        assert_eq!(
+            drivers(&missing, &metadata),
+            [("missing".to_string(), Ok(Driver::Mssql))]
+        );
     }
 }